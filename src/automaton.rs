@@ -0,0 +1,199 @@
+//! Automata with fixed rules that are known by name rather than written as a
+//! rulestring, like [WireWorld](https://conwaylife.com/wiki/WireWorld).
+
+use std::fmt;
+
+use bevy::math::IVec2;
+use bevy::utils::HashMap;
+
+/// The first state with an ant on it, states from here on are an ant facing up,
+/// right, down or left on a dead cell, then the same on an alive one.
+const ANT: u8 = 2;
+
+/// The way an ant facing each direction moves, with y up.
+const FORWARD: [(i32, i32); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Automaton {
+    /// Electrons moving along wires, with the states empty, electron head,
+    /// electron tail and conductor.
+    WireWorld,
+    /// [Langton's Ant](https://conwaylife.com/wiki/Langton%27s_ant), which turns
+    /// right on dead cells and left on alive ones, flipping the cell it leaves.
+    LangtonsAnt,
+}
+
+impl Automaton {
+    pub fn states(&self) -> u8 {
+        match self {
+            Automaton::WireWorld => 4,
+            Automaton::LangtonsAnt => ANT + 8,
+        }
+    }
+
+    /// The state a cell in `state` moves to with `alive_neighbours` neighbours in
+    /// state `1`. Ants move with `step_ants` instead, so this leaves their cells as they are.
+    pub fn next_state(&self, state: u8, alive_neighbours: u32) -> u8 {
+        match (self, state) {
+            (Automaton::WireWorld, 1) => 2,
+            (Automaton::WireWorld, 2) => 3,
+            (Automaton::WireWorld, 3) if (1..=2).contains(&alive_neighbours) => 1,
+            _ => state,
+        }
+    }
+
+    /// The colour of each state as RGB.
+    pub fn palette(&self) -> &'static [[u8; 3]] {
+        match self {
+            Automaton::WireWorld => &[[0, 0, 0], [64, 128, 255], [255, 255, 255], [255, 128, 0]],
+            Automaton::LangtonsAnt => &[
+                [0, 0, 0],
+                [255, 255, 255],
+                [255, 0, 0],
+                [255, 0, 0],
+                [255, 0, 0],
+                [255, 0, 0],
+                [255, 96, 96],
+                [255, 96, 96],
+                [255, 96, 96],
+                [255, 96, 96],
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Automaton {
+    /// Formats the name the way Golly does.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Automaton::WireWorld => write!(f, "WireWorld"),
+            Automaton::LangtonsAnt => write!(f, "Langtons-Ant"),
+        }
+    }
+}
+
+impl std::str::FromStr for Automaton {
+    type Err = ();
+
+    /// Parses a name ignoring case, spaces and punctuation, so `Langton's Ant` works too.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "wireworld" => Ok(Automaton::WireWorld),
+            "langtonsant" => Ok(Automaton::LangtonsAnt),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Moves every ant one cell forward, after turning and flipping the cell it was on.
+/// Ants that end up on the same cell merge into one.
+///
+/// `wrap` gives the cell an ant moving to a position ends up on, `None` if it
+/// falls off the edge of the universe.
+pub(crate) fn step_ants(
+    cells: &HashMap<IVec2, u8>,
+    wrap: impl Fn(IVec2) -> Option<IVec2>,
+) -> HashMap<IVec2, u8> {
+    let mut next = cells
+        .iter()
+        .filter(|(_, &state)| state < ANT)
+        .map(|(&pos, &state)| (pos, state))
+        .collect::<HashMap<_, _>>();
+
+    let mut arrivals: HashMap<IVec2, u8> = HashMap::default();
+    for (&pos, &state) in cells.iter().filter(|(_, &state)| state >= ANT) {
+        let on_alive = state >= ANT + 4;
+        let facing = (state - ANT) % 4;
+        let facing = if on_alive {
+            (facing + 3) % 4
+        } else {
+            (facing + 1) % 4
+        };
+        if on_alive {
+            next.remove(&pos);
+        } else {
+            next.insert(pos, 1);
+        }
+        // Keep the same ant whatever order they're visited in.
+        let to = match wrap(pos + IVec2::from(FORWARD[facing as usize])) {
+            Some(to) => to,
+            None => continue,
+        };
+        let arrived = arrivals.entry(to).or_insert(facing);
+        *arrived = (*arrived).min(facing);
+    }
+
+    for (pos, facing) in arrivals {
+        let on_alive = next.get(&pos) == Some(&1);
+        next.insert(pos, ANT + facing + 4 * on_alive as u8);
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stochastic::Randomness;
+    use crate::universe::Universe;
+
+    /// A WireWorld diode between two wires, with an electron sent along one of them.
+    fn diode(from_left: bool) -> Universe {
+        let mut universe = Universe::new("WireWorld".parse().unwrap());
+        universe.set_rules("WireWorld".parse().unwrap());
+        let diode = [(0, 0), (1, 0), (1, 1), (2, 1), (1, -1), (2, -1)];
+        let wires = (-8..=-1).chain(3..=10).map(|x| (x, 0));
+        for pos in wires.chain(diode) {
+            universe.set_state(IVec2::from(pos), 3);
+        }
+        let (head, tail) = if from_left { (-7, -8) } else { (9, 10) };
+        universe.set_state(IVec2::new(head, 0), 1);
+        universe.set_state(IVec2::new(tail, 0), 2);
+        universe
+    }
+
+    #[test]
+    fn diodes_pass_one_way() {
+        let mut randomness = Randomness::new(0);
+        for (from_left, end) in [(true, 9), (false, -7)] {
+            let mut universe = diode(from_left);
+            let mut arrived = false;
+            for _ in 0..30 {
+                universe.step(&mut randomness);
+                arrived |= universe.state(IVec2::new(end, 0)) == 1;
+            }
+            assert_eq!(arrived, from_left);
+        }
+    }
+
+    #[test]
+    fn ants_turn_and_flip() {
+        let mut universe = Universe::new("Langton's Ant".parse().unwrap());
+        universe.set_rules("Langton's Ant".parse().unwrap());
+        universe.set_state(IVec2::ZERO, ANT);
+        let mut randomness = Randomness::new(0);
+        // Right around a square of dead cells, then left off the first one.
+        for _ in 0..5 {
+            universe.step(&mut randomness);
+        }
+        let mut cells = universe
+            .iter_states()
+            .map(|(pos, state)| (pos.x, pos.y, state))
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        assert_eq!(cells, [(-1, 0, ANT + 3), (0, -1, 1), (1, -1, 1), (1, 0, 1)]);
+    }
+
+    #[test]
+    fn names() {
+        for name in ["WireWorld", "wireworld", "Langtons-Ant", "Langton's Ant"] {
+            let automaton = name.parse::<Automaton>().unwrap();
+            assert_eq!(automaton.to_string().parse(), Ok(automaton));
+        }
+        assert!("Wire".parse::<Automaton>().is_err());
+    }
+}
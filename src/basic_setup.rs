@@ -61,7 +61,7 @@ fn cursor_transform(
 }
 
 fn cursor_to_world(window: &Window, cam_transform: &Transform, cursor_position: Vec2) -> Vec2 {
-    let window_size = Vec2::new(window.width() as f32, window.height() as f32);
+    let window_size = Vec2::new(window.width(), window.height());
 
     // (0, 0) is in the middle of the screen
     let screen_position = cursor_position - window_size / 2.0;
@@ -82,9 +82,6 @@ fn basic_setup(mut commands: Commands) {
         .insert_bundle((Transform::default(), GlobalTransform::default(), Cursor));
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
-pub struct BasicSetup;
-
 pub(crate) struct BasicSetupPlugin;
 impl Plugin for BasicSetupPlugin {
     fn build(&self, app: &mut App) {
@@ -105,7 +102,7 @@ impl Plugin for BasicSetupPlugin {
                 ..Default::default()
             })
             .add_plugins(DefaultPlugins)
-            .add_plugin(FrameTimeDiagnosticsPlugin::default())
+            .add_plugin(FrameTimeDiagnosticsPlugin)
             .add_startup_system(basic_setup)
             .add_system(cursor_state)
             .add_system(cursor_transform);
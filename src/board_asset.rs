@@ -1,38 +1,408 @@
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
 use bevy::{
-    asset::{AssetLoader, LoadedAsset},
+    asset::{AssetLoader, LoadContext, LoadedAsset},
     prelude::*,
     reflect::TypeUuid,
 };
 
+use automata::{
+    hashlife::HashLife,
+    macrocell::{self, MacrocellCells, MacrocellError},
+    rules::GameRules,
+    ruletable::{RuleTable, RuleTableError},
+    universe::Universe,
+};
+use flate2::read::GzDecoder;
+
+use crate::message::ShowMessage;
+
 pub(crate) struct BoardAssetPlugin;
 impl Plugin for BoardAssetPlugin {
     fn build(&self, app: &mut App) {
+        let errors = LoadErrors::default();
         app.add_asset::<BoardAsset>()
-            .add_asset_loader(BoardAssetLoader);
+            .insert_resource(errors.clone())
+            .add_asset_loader(BoardAssetLoader { errors })
+            .add_system(report_load_errors);
     }
 }
 
-fn parse_cells(bytes: &[u8]) -> anyhow::Result<BoardAsset> {
-    let raw_data = std::str::from_utf8(bytes)?;
-    let board_data: Vec<Vec<bool>> = raw_data
-        .lines()
-        .filter(|l| !l.starts_with('!'))
+/// A position in a pattern file, both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    fn line(line: usize) -> Self {
+        Self { line, column: 1 }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug)]
+pub enum PatternError {
+    /// The file's extension isn't one of the formats that can be loaded.
+    UnknownFormat(String),
+    BadUtf8(Location),
+    /// The header, or a line describing the pattern rather than its cells.
+    BadHeader {
+        at: Location,
+        expected: String,
+    },
+    /// A run or row of cells.
+    BadRun {
+        at: Location,
+        expected: String,
+    },
+    /// The file has nothing in it.
+    EmptyPattern,
+    Macrocell(MacrocellError),
+    RuleTable(RuleTableError),
+    /// The pattern's rule isn't one that's built in and there's no `.rule` file
+    /// for it next to the pattern.
+    UnknownRule {
+        name: String,
+        error: String,
+    },
+    Gzip(std::io::Error),
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternError::UnknownFormat(extension) if extension.is_empty() => {
+                write!(f, "no file extension to tell the pattern format from")
+            }
+            PatternError::UnknownFormat(extension) => {
+                write!(f, "unknown pattern format `.{}`", extension)
+            }
+            PatternError::BadUtf8(at) => write!(f, "{}: invalid UTF-8", at),
+            PatternError::BadHeader { at, expected } => {
+                write!(f, "{}: bad header, expected {}", at, expected)
+            }
+            PatternError::BadRun { at, expected } => {
+                write!(f, "{}: bad run of cells, expected {}", at, expected)
+            }
+            PatternError::EmptyPattern => write!(f, "the file is empty"),
+            PatternError::Macrocell(e) => write!(f, "{}", e),
+            PatternError::RuleTable(e) => write!(f, "{}", e),
+            PatternError::UnknownRule { name, error } => {
+                write!(
+                    f,
+                    "unknown rule `{}` and couldn't read `{}.rule`: {}",
+                    name, name, error
+                )
+            }
+            PatternError::Gzip(e) => write!(f, "couldn't decompress: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+/// Patterns that failed to load along with why, shared with the loader so they
+/// can be shown in the app.
+#[derive(Debug, Clone, Default)]
+pub struct LoadErrors(Arc<Mutex<Vec<(String, PatternError)>>>);
+
+fn report_load_errors(errors: Res<LoadErrors>, mut messages: EventWriter<ShowMessage>) {
+    for (path, error) in errors.0.lock().unwrap().drain(..) {
+        let message = format!("Couldn't load {}: {}", path, error);
+        eprintln!("{}", message);
+        messages.send(ShowMessage(message));
+    }
+}
+
+/// Checks that `bytes` is UTF-8, pointing at the first invalid byte if it isn't.
+fn decode(bytes: &[u8]) -> Result<&str, PatternError> {
+    std::str::from_utf8(bytes).map_err(|e| {
+        let valid = &bytes[..e.valid_up_to()];
+        let line_start = valid.iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let column = String::from_utf8_lossy(&valid[line_start..])
+            .chars()
+            .count()
+            + 1;
+        PatternError::BadUtf8(Location {
+            line: valid.iter().filter(|&&b| b == b'\n').count() + 1,
+            column,
+        })
+    })
+}
+
+fn parse_cells(bytes: &[u8]) -> Result<BoardAsset, PatternError> {
+    let raw_data = decode(bytes)?;
+    let (metadata, pattern): (Vec<&str>, Vec<&str>) =
+        raw_data.lines().partition(|l| l.starts_with('!'));
+
+    let board_data: Vec<Vec<bool>> = pattern
+        .iter()
         .map(|line| line.chars().map(|c| matches!(c, '■' | 'O')).collect())
         .collect();
     let board_dimensions = (
-        raw_data
-            .lines()
-            .fold(0, |acc, x| acc.max(x.chars().count())) as u32,
-        raw_data.lines().count() as u32,
+        pattern.iter().fold(0, |acc, x| acc.max(x.chars().count())) as u32,
+        pattern.len() as u32,
     );
 
-    Ok(BoardAsset {
-        data: board_data,
-        size: board_dimensions,
-    })
+    let mut board = BoardAsset::from_rows(&board_data, board_dimensions, None);
+    for line in metadata {
+        let line = line[1..].trim();
+        match line.strip_prefix("Name:") {
+            Some(name) => board.name = Some(name.trim().to_string()),
+            None => board.comments.push(line.to_string()),
+        }
+    }
+    Ok(board)
+}
+
+/// Writes the cells of `universe` within `region`, or the bounding box of every
+/// alive cell if there isn't one, as a plaintext `.cells` file.
+///
+/// `name` becomes the `!Name:` line and each of `comments` a `!` line.
+pub(crate) fn write_cells(
+    universe: &Universe,
+    region: Option<(IVec2, IVec2)>,
+    name: &str,
+    comments: &[String],
+) -> String {
+    let mut out = format!("!Name: {}\n", name);
+    for comment in comments {
+        out.push_str(&format!("!{}\n", comment));
+    }
+
+    if let Some((min, max)) = region.or_else(|| universe.bounding_box()) {
+        // The top row of the file is the highest row of the board.
+        for y in (min.y..=max.y).rev() {
+            let row = (min.x..=max.x)
+                .map(|x| {
+                    if universe.get(IVec2::new(x, y)) {
+                        'O'
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            out.push_str(&row);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn parse_macrocell(bytes: &[u8]) -> Result<BoardAsset, PatternError> {
+    let input = decode(bytes)?;
+    let pattern = macrocell::parse(input).map_err(PatternError::Macrocell)?;
+    // Macrocell patterns are placed where Golly places them.
+    let mut board = match pattern.cells {
+        MacrocellCells::Tree(life) => {
+            let mut board = BoardAsset::placed(Vec::new(), pattern.rule);
+            board.tree = Some(life);
+            board
+        }
+        MacrocellCells::States(cells) => {
+            let cells = cells
+                .into_iter()
+                .map(|(pos, state)| (IVec2::new(pos.x, -pos.y), state));
+            BoardAsset::placed(cells.collect(), pattern.rule)
+        }
+    };
+    board.rule_file = pattern.rule_file;
+    board.generation = pattern.generation;
+    Ok(board)
+}
+
+/// Parses a `#Life 1.05` file, a list of blocks of `*` and `.` cells each placed
+/// by a `#P x y` line.
+fn parse_life_105(input: &str) -> Result<BoardAsset, PatternError> {
+    let mut cells = Vec::new();
+    let mut rule = None;
+    let mut comments = Vec::new();
+    let mut block = IVec2::ZERO;
+    let mut y = 0;
+    for (number, line) in input.lines().enumerate().skip(1) {
+        let line = line.trim_end();
+        let bad_header = |expected: String| PatternError::BadHeader {
+            at: Location::line(number + 1),
+            expected,
+        };
+        if let Some(description) = line.strip_prefix("#D") {
+            comments.push(description.trim().to_string());
+        } else if line.starts_with("#N") {
+            rule = Some(GameRules::conway());
+        } else if let Some(r) = line.strip_prefix("#R") {
+            rule = Some(
+                r.trim()
+                    .parse()
+                    .map_err(|e| bad_header(format!("a rule, {}", e)))?,
+            );
+        } else if let Some(position) = line.strip_prefix("#P") {
+            let coords = position
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<Vec<i32>, _>>();
+            match coords.as_deref() {
+                Ok(&[x, y]) => block = IVec2::new(x, y),
+                _ => return Err(bad_header("`#P x y`".to_string())),
+            }
+            y = 0;
+        } else if line.starts_with('#') {
+            continue;
+        } else {
+            for (x, c) in line.chars().enumerate() {
+                match c {
+                    '*' => cells.push((block + IVec2::new(x as i32, y), 1)),
+                    '.' => {}
+                    _ => {
+                        return Err(PatternError::BadRun {
+                            at: Location {
+                                line: number + 1,
+                                column: x + 1,
+                            },
+                            expected: "`*` or `.`".to_string(),
+                        })
+                    }
+                }
+            }
+            y += 1;
+        }
+    }
+
+    let mut board = BoardAsset::placed(cells, rule);
+    board.comments = comments;
+    Ok(board)
+}
+
+/// Parses a `#Life 1.06` file, which has one `x y` coordinate per line.
+fn parse_life_106(input: &str) -> Result<BoardAsset, PatternError> {
+    let mut cells = Vec::new();
+    for (number, line) in input.lines().enumerate().skip(1) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let coords = line
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<i32>, _>>();
+        match coords.as_deref() {
+            Ok(&[x, y]) => cells.push((IVec2::new(x, y), 1)),
+            _ => {
+                return Err(PatternError::BadRun {
+                    at: Location::line(number + 1),
+                    expected: "an `x y` coordinate".to_string(),
+                })
+            }
+        }
+    }
+    Ok(BoardAsset::placed(cells, None))
+}
+
+/// Parses either version of the Life format from its header line.
+fn parse_life(bytes: &[u8]) -> Result<BoardAsset, PatternError> {
+    let input = decode(bytes)?;
+    match input.lines().next().map(str::trim) {
+        Some("#Life 1.05") => parse_life_105(input),
+        Some("#Life 1.06") => parse_life_106(input),
+        _ => Err(PatternError::BadHeader {
+            at: Location::line(1),
+            expected: "`#Life 1.05` or `#Life 1.06`".to_string(),
+        }),
+    }
+}
+
+/// Reads a Golly `.rule` file as an empty pattern that runs its rule.
+fn parse_rule_file(bytes: &[u8]) -> Result<BoardAsset, PatternError> {
+    let table = RuleTable::parse(decode(bytes)?).map_err(PatternError::RuleTable)?;
+    let mut board = BoardAsset::from_rows(&[], (0, 0), None);
+    board.name = Some(table.name.clone());
+    board.rule = Some(GameRules::Table(Arc::new(table)));
+    Ok(board)
+}
+
+/// Reads the rule of a pattern that names one that isn't built in from the
+/// `.rule` file of that name next to it, like Golly does.
+async fn load_rule_file(
+    mut board: BoardAsset,
+    load_context: &LoadContext<'_>,
+) -> Result<BoardAsset, PatternError> {
+    let name = match board.rule_file.take() {
+        Some(name) => name,
+        None => return Ok(board),
+    };
+    let path = load_context.path().with_file_name(format!("{}.rule", name));
+    let bytes = match load_context.read_asset_bytes(&path).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            return Err(PatternError::UnknownRule {
+                name,
+                error: e.to_string(),
+            })
+        }
+    };
+    let table = RuleTable::parse(decode(&bytes)?).map_err(PatternError::RuleTable)?;
+    board.rule = Some(GameRules::Table(Arc::new(table)));
+    Ok(board)
+}
+
+/// Whether `bytes` starts with the gzip magic number.
+fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.starts_with(&[0x1f, 0x8b])
+}
+
+/// Works out the format of a pattern file and parses it.
+fn load_pattern(path: &Path, bytes: &[u8]) -> Result<BoardAsset, PatternError> {
+    let mut extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+    // `pattern.mc.gz` is loaded as `pattern.mc` once it's decompressed.
+    let decompressed;
+    let bytes = if is_gzip(bytes) {
+        let mut out = Vec::new();
+        GzDecoder::new(bytes)
+            .read_to_end(&mut out)
+            .map_err(PatternError::Gzip)?;
+        decompressed = out;
+        if extension == "gz" {
+            extension = path
+                .file_stem()
+                .and_then(|stem| Path::new(stem).extension())
+                .and_then(|e| e.to_str())
+                .unwrap_or("mc");
+        }
+        &decompressed[..]
+    } else {
+        bytes
+    };
+
+    if bytes.iter().all(u8::is_ascii_whitespace) {
+        return Err(PatternError::EmptyPattern);
+    }
+
+    // Life files are recognised by their header whatever they're called.
+    match extension {
+        _ if bytes.starts_with(b"#Life 1.0") => parse_life(bytes),
+        "lif" | "life" => parse_life(bytes),
+        "rle" => rle::parse(decode(bytes)?),
+        "board" | "cells" => parse_cells(bytes),
+        "mc" => parse_macrocell(bytes),
+        "rule" => parse_rule_file(bytes),
+        _ => Err(PatternError::UnknownFormat(extension.to_string())),
+    }
+}
+
+struct BoardAssetLoader {
+    errors: LoadErrors,
 }
 
-struct BoardAssetLoader;
 impl AssetLoader for BoardAssetLoader {
     fn load<'a>(
         &'a self,
@@ -40,81 +410,661 @@ impl AssetLoader for BoardAssetLoader {
         load_context: &'a mut bevy::asset::LoadContext,
     ) -> bevy::asset::BoxedFuture<'a, Result<(), anyhow::Error>> {
         Box::pin(async move {
-            let board = match load_context.path().extension().unwrap().to_str().unwrap() {
-                "rle" => {
-                    let input = String::from_utf8(bytes.to_vec())?;
-                    rle::parse(&input)?
-                }
-                "board" | "cells" => parse_cells(bytes)?,
-                _ => unimplemented!(),
+            let path = load_context.path().to_owned();
+            let board = match load_pattern(&path, bytes) {
+                Ok(board) => load_rule_file(board, load_context).await,
+                Err(error) => Err(error),
             };
-
-            load_context.set_default_asset(LoadedAsset::new(board));
-            Ok(())
+            match board {
+                Ok(board) => {
+                    load_context.set_default_asset(LoadedAsset::new(board));
+                    Ok(())
+                }
+                Err(error) => {
+                    let message = error.to_string();
+                    let path = path.display().to_string();
+                    self.errors.0.lock().unwrap().push((path, error));
+                    Err(anyhow::anyhow!(message))
+                }
+            }
         })
     }
 
     fn extensions(&self) -> &[&str] {
-        &["board", "rle", "cells"]
+        &["board", "rle", "cells", "mc", "gz", "lif", "life", "rule"]
     }
 }
 
 #[derive(TypeUuid, Clone)]
 #[uuid = "579f4885-5a11-46d3-a7e6-5528e254c836"]
 pub struct BoardAsset {
-    pub data: Vec<Vec<bool>>,
+    /// The cells that aren't dead and their states, with y increasing downwards.
+    pub cells: Vec<(IVec2, u8)>,
     pub size: (u32, u32),
+    /// The rule the pattern was made for, if the file specifies one.
+    pub rule: Option<GameRules>,
+    /// The name of a rule that isn't built in, to be read from a `.rule` file.
+    pub rule_file: Option<String>,
+    /// A two state pattern read as a HashLife tree, with y increasing upwards,
+    /// which is added as it is rather than a cell at a time.
+    pub tree: Option<HashLife>,
+    /// The generation the pattern was saved at.
+    pub generation: u64,
+    pub name: Option<String>,
+    pub comments: Vec<String>,
+    /// Whether `cells` are at the positions the file placed them, rather than
+    /// relative to the top left of the pattern, so it should be centered.
+    pub absolute: bool,
+}
+
+impl BoardAsset {
+    fn from_rows(rows: &[Vec<bool>], size: (u32, u32), rule: Option<GameRules>) -> Self {
+        let cells = rows
+            .iter()
+            .enumerate()
+            .flat_map(|(y, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|(_, &alive)| alive)
+                    .map(move |(x, _)| (IVec2::new(x as i32, y as i32), 1))
+            })
+            .collect();
+        Self {
+            cells,
+            size,
+            rule,
+            rule_file: None,
+            tree: None,
+            generation: 0,
+            name: None,
+            comments: Vec::new(),
+            absolute: false,
+        }
+    }
+
+    /// A pattern whose cells are at the positions the file gives, which can be negative.
+    fn placed(cells: Vec<(IVec2, u8)>, rule: Option<GameRules>) -> Self {
+        let size = match (
+            cells.iter().map(|c| c.0).reduce(IVec2::min),
+            cells.iter().map(|c| c.0).reduce(IVec2::max),
+        ) {
+            (Some(min), Some(max)) => ((max.x - min.x + 1) as u32, (max.y - min.y + 1) as u32),
+            _ => (0, 0),
+        };
+        Self {
+            cells,
+            size,
+            rule,
+            rule_file: None,
+            tree: None,
+            generation: 0,
+            name: None,
+            comments: Vec::new(),
+            absolute: true,
+        }
+    }
 }
 
-mod rle {
-    use super::BoardAsset;
+pub(crate) mod rle {
+    use super::{BoardAsset, Location, PatternError};
+    use automata::rules::{GameRules, RuleParseError};
+    use automata::ruletable::RuleTable;
+    use automata::universe::Universe;
+    use bevy::{math::IVec2, utils::HashMap};
 
-    enum State {
-        Alive,
-        Dead,
+    /// The longest line `write` will output, as recommended by the format.
+    const LINE_LENGTH: usize = 70;
+
+    /// What the `#` lines of a file say about its pattern.
+    #[derive(Default)]
+    struct Metadata {
+        name: Option<String>,
+        comments: Vec<String>,
+        /// Where the top left of the pattern goes, from a `#P` or `#R` line.
+        offset: Option<IVec2>,
+        /// A rule from a `#r` line, used if the header doesn't have one.
+        rule: Option<Rule>,
     }
 
-    pub use parser::parse;
+    /// A rule from a header or `#r` line.
+    enum Rule {
+        BuiltIn(GameRules),
+        /// The name of a rule that isn't built in, to be read from a `.rule` file.
+        File(String),
+    }
 
-    peg::parser! {
-        pub grammar parser() for str {
-            rule _ -> ()
-                = " " / "\t" / "\n" / "\r\n"
+    impl Rule {
+        fn parse(rule: &str) -> Result<Rule, RuleParseError> {
+            match rule.parse() {
+                Ok(rule) => Ok(Rule::BuiltIn(rule)),
+                Err(_) if RuleTable::is_name(rule) => Ok(Rule::File(rule.to_string())),
+                Err(e) => Err(e),
+            }
+        }
+    }
 
-            rule number() -> usize
-                = n:$(['0'..='9']+) {? n.parse().or(Err("usize")) }
+    impl Metadata {
+        fn read(&mut self, number: usize, line: &str) -> Result<(), PatternError> {
+            let bad_line = |expected: &str| PatternError::BadHeader {
+                at: Location::line(number),
+                expected: expected.to_string(),
+            };
+            let mut chars = line[1..].chars();
+            let kind = chars.next();
+            let text = chars.as_str().trim();
+            match kind {
+                Some('N') => self.name = Some(text.to_string()),
+                Some('C' | 'c' | 'O') => self.comments.push(text.to_string()),
+                Some('P' | 'R') => {
+                    let coords = text
+                        .split_whitespace()
+                        .map(str::parse)
+                        .collect::<Result<Vec<i32>, _>>();
+                    match coords.as_deref() {
+                        Ok(&[x, y]) => self.offset = Some(IVec2::new(x, y)),
+                        _ => return Err(bad_line("`#P x y`")),
+                    }
+                }
+                Some('r') => {
+                    let rule =
+                        Rule::parse(text).map_err(|e| bad_line(&format!("a rule, {}", e)))?;
+                    self.rule = Some(rule);
+                }
+                _ => {}
+            }
+            Ok(())
+        }
+    }
 
-            rule comment() -> ()
-                = "#" [^ '\n']+ "\n"
+    /// Reads an `x = m, y = n, rule = abc` header. Keys other than these are
+    /// ignored and the rule is always the rest of the line, as it can have commas.
+    fn parse_header(number: usize, line: &str) -> Result<((u32, u32), Option<Rule>), PatternError> {
+        let mut width = None;
+        let mut height = None;
+        let mut rule = None;
+        let mut rest = line;
+        while !rest.trim().is_empty() {
+            let at = Location {
+                line: number,
+                column: line[..line.len() - rest.len()].chars().count() + 1,
+            };
+            let bad_header = |expected: String| PatternError::BadHeader { at, expected };
+            let (key, value) = rest
+                .split_once('=')
+                .ok_or_else(|| bad_header("`key = value`".to_string()))?;
+            let key = key.trim();
+            let (value, next) = match key {
+                "rule" => (value, ""),
+                _ => value.split_once(',').unwrap_or((value, "")),
+            };
+            let value = value.trim();
+            match key {
+                "x" => width = Some(value.parse().map_err(|_| bad_header("a width".into()))?),
+                "y" => height = Some(value.parse().map_err(|_| bad_header("a height".into()))?),
+                "rule" => {
+                    let parsed =
+                        Rule::parse(value).map_err(|e| bad_header(format!("a rule, {}", e)))?;
+                    rule = Some(parsed);
+                }
+                _ => {}
+            }
+            rest = next;
+        }
+        match (width, height) {
+            (Some(width), Some(height)) => Ok(((width, height), rule)),
+            _ => Err(PatternError::BadHeader {
+                at: Location::line(number),
+                expected: "`x = m, y = n`".to_string(),
+            }),
+        }
+    }
 
-            rule header_line() -> ()
-                = [^ '\n']+ "\n"
+    /// Parses an RLE file.
+    ///
+    /// Following Golly, whitespace and line breaks can go anywhere in the runs,
+    /// even inside a count, any letter other than `b` is an alive cell and
+    /// anything after the final `!` is ignored.
+    pub fn parse(input: &str) -> Result<BoardAsset, PatternError> {
+        let mut lines = input
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim_end()));
+        let mut metadata = Metadata::default();
 
-            rule state() -> State
-                = s:['o' | 'b'] { if s == 'o' {State::Alive} else {State::Dead} }
+        let (size, header_rule) = loop {
+            match lines.next() {
+                Some((_, line)) if line.trim().is_empty() => {}
+                Some((number, line)) if line.starts_with('#') => metadata.read(number, line)?,
+                Some((number, line)) => break parse_header(number, line)?,
+                None => {
+                    return Err(PatternError::BadHeader {
+                        at: Location::line(input.lines().count() + 1),
+                        expected: "`x = m, y = n`".to_string(),
+                    })
+                }
+            }
+        };
 
-            rule rle_statement() -> (usize, State)
-                = _* l:number()? s:state() _* { (l.unwrap_or(1), s) }
+        let mut cells = Vec::new();
+        let mut pos = IVec2::ZERO;
+        let mut count: Option<i32> = None;
+        // The `p` to `y` that starts a two letter state.
+        let mut prefix: Option<char> = None;
+        'lines: for (number, line) in lines {
+            if line.starts_with('#') {
+                metadata.read(number, line)?;
+                continue;
+            }
+            for (column, c) in line.chars().enumerate() {
+                let bad_run = |expected: &str| PatternError::BadRun {
+                    at: Location {
+                        line: number,
+                        column: column + 1,
+                    },
+                    expected: expected.to_string(),
+                };
+                let mut place = |state: u8, n: Option<i32>| {
+                    let n = n.unwrap_or(1);
+                    if state != 0 {
+                        cells.extend((0..n).map(|dx| (pos + IVec2::new(dx, 0), state)));
+                    }
+                    pos.x = pos
+                        .x
+                        .checked_add(n)
+                        .ok_or_else(|| bad_run("a shorter row"))?;
+                    Ok(())
+                };
 
-            rule line() -> Vec<bool>
-                = xs:rle_statement()+ f:number()? ("$" / "!") { let mut v: Vec<_> = xs.into_iter().flat_map(|(count, state)| match state {
-                    State::Alive => vec![true; count],
-                    State::Dead => vec![false; count],
-                }).collect();
-                if let Some(f) = f {
-                    v.append(&mut vec![false; f])
+                if let Some(p) = prefix.take() {
+                    if c.is_ascii_uppercase() {
+                        let state = extended_state(Some(p), c)
+                            .ok_or_else(|| bad_run("a state below 256"))?;
+                        place(state, count.take())?;
+                        continue;
+                    }
+                    // Without a state letter after it, it's an alive cell like any other letter.
+                    place(1, count.take())?;
                 }
-                v}
 
-            pub rule parse() -> BoardAsset
-                = comment()* header_line() comment()* lines:line()+ "\n"? { let sizes = (
-                    lines.iter().map(|l| l.len()).max().unwrap() as u32,
-                    lines.len() as u32,
-                );
+                if let Some(digit) = c.to_digit(10) {
+                    count = count
+                        .unwrap_or(0)
+                        .checked_mul(10)
+                        .and_then(|n| n.checked_add(digit as i32))
+                        .map(Some)
+                        .ok_or_else(|| bad_run("a shorter run"))?;
+                    continue;
+                }
+                match c {
+                    _ if c.is_whitespace() => {}
+                    '!' => break 'lines,
+                    '$' => {
+                        let n = count.take().unwrap_or(1);
+                        pos = IVec2::new(
+                            0,
+                            pos.y.checked_add(n).ok_or_else(|| bad_run("fewer rows"))?,
+                        );
+                    }
+                    'b' | '.' => place(0, count.take())?,
+                    'p'..='y' => prefix = Some(c),
+                    'A'..='X' => place(extended_state(None, c).unwrap(), count.take())?,
+                    _ if c.is_ascii_alphabetic() => place(1, count.take())?,
+                    _ => return Err(bad_run("a run of cells, `$` or `!`")),
+                }
+            }
+        }
+
+        let (rule, rule_file) = match header_rule.or(metadata.rule) {
+            Some(Rule::BuiltIn(rule)) => (Some(rule), None),
+            Some(Rule::File(name)) => (None, Some(name)),
+            None => (None, None),
+        };
+        let mut board = match metadata.offset {
+            Some(offset) => {
+                let cells = cells.into_iter().map(|(c, state)| (c + offset, state));
+                BoardAsset::placed(cells.collect(), rule)
+            }
+            None => {
+                // The header's size is only a hint, the cells can go past it.
+                let (width, height) = cells.iter().fold(size, |(w, h), (c, _)| {
+                    (w.max(c.x as u32 + 1), h.max(c.y as u32 + 1))
+                });
                 BoardAsset {
-                    data: lines,
-                    size: sizes
-                } }
+                    cells,
+                    size: (width, height),
+                    rule,
+                    rule_file: None,
+                    tree: None,
+                    generation: 0,
+                    name: None,
+                    comments: Vec::new(),
+                    absolute: false,
+                }
+            }
+        };
+        board.rule_file = rule_file;
+        board.name = metadata.name;
+        board.comments = metadata.comments;
+        Ok(board)
+    }
+
+    /// The state of a letter of Golly's extended alphabet, `A` to `X` are 1 to 24
+    /// and each prefix from `p` to `y` adds another 24.
+    fn extended_state(prefix: Option<char>, letter: char) -> Option<u8> {
+        let high = prefix.map_or(0, |p| p as u32 - 'p' as u32 + 1);
+        let state = 24 * high + letter as u32 - 'A' as u32 + 1;
+        u8::try_from(state).ok()
+    }
+
+    /// The letters `state` is written as, the inverse of `extended_state`.
+    fn extended_letters(state: u8) -> String {
+        let (high, low) = ((state - 1) / 24, (state - 1) % 24);
+        let letter = char::from(b'A' + low);
+        match high {
+            0 => letter.to_string(),
+            _ => format!("{}{}", char::from(b'p' + high - 1), letter),
+        }
+    }
+
+    fn run(count: usize, tag: &str) -> String {
+        if count == 1 {
+            tag.to_string()
+        } else {
+            format!("{}{}", count, tag)
+        }
+    }
+
+    /// Writes the cells of `universe` that aren't dead as an RLE file of their
+    /// bounding box. Patterns with more states than alive and dead use Golly's
+    /// extended alphabet of `.` and `A` to `yO`.
+    ///
+    /// `name` becomes the `#N` line and each of `comments` a `#C` line.
+    pub fn write(universe: &Universe, name: &str, comments: &[String]) -> String {
+        let mut rows: HashMap<i32, Vec<(i32, u8)>> = HashMap::default();
+        for (pos, state) in universe.iter_states() {
+            rows.entry(pos.y).or_default().push((pos.x, state));
+        }
+        let xs = || rows.values().flatten().map(|&(x, _)| x);
+        let min_x = xs().min().unwrap_or(0);
+        let max_x = xs().max().unwrap_or(-1);
+        let min_y = rows.keys().copied().min().unwrap_or(0);
+        let max_y = rows.keys().copied().max().unwrap_or(-1);
+
+        let extended =
+            universe.rules().states() > 2 || rows.values().flatten().any(|&(_, state)| state > 1);
+        let dead = if extended { "." } else { "b" };
+        let tag = |state: u8| {
+            if extended {
+                extended_letters(state)
+            } else {
+                "o".to_string()
+            }
+        };
+
+        // The top row of the file is the highest row of the board.
+        let mut ys = rows.keys().copied().collect::<Vec<_>>();
+        ys.sort_unstable_by(|a, b| b.cmp(a));
+
+        let mut tokens = Vec::new();
+        let mut previous_y = max_y;
+        for y in ys {
+            if y != previous_y {
+                tokens.push(run((previous_y - y) as usize, "$"));
+            }
+            previous_y = y;
+
+            let row = rows.get_mut(&y).unwrap();
+            row.sort_unstable();
+            let mut x = min_x;
+            let mut i = 0;
+            while i < row.len() {
+                let (start, state) = row[i];
+                if start > x {
+                    tokens.push(run((start - x) as usize, dead));
+                }
+                while i + 1 < row.len() && row[i + 1] == (row[i].0 + 1, state) {
+                    i += 1;
+                }
+                tokens.push(run((row[i].0 - start + 1) as usize, &tag(state)));
+                x = row[i].0 + 1;
+                i += 1;
+            }
+        }
+        tokens.push("!".to_string());
+        let mut out = format!("#N {}\n", name);
+        for comment in comments {
+            out.push_str(&format!("#C {}\n", comment));
+        }
+        out.push_str(&format!(
+            "x = {}, y = {}, rule = {}\n",
+            max_x - min_x + 1,
+            max_y - min_y + 1,
+            universe.rules()
+        ));
+
+        let mut line_length = 0;
+        for token in tokens {
+            if line_length + token.len() > LINE_LENGTH {
+                out.push('\n');
+                line_length = 0;
+            }
+            line_length += token.len();
+            out.push_str(&token);
+        }
+        out.push('\n');
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use automata::stochastic::Randomness;
+    use automata::universe::Engine;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /// A universe running `rule` from a random soup, a few generations in.
+    fn soup(rule: &str) -> Universe {
+        let mut universe = Universe::new(GameRules::conway());
+        universe.set_rules(rule.parse().unwrap());
+        let mut rng = StdRng::seed_from_u64(9);
+        for _ in 0..400 {
+            let pos = IVec2::new(rng.gen_range(-30..25), rng.gen_range(-15..40));
+            universe.set(pos, true);
+        }
+        let mut randomness = Randomness::new(0);
+        for _ in 0..5 {
+            universe.step(&mut randomness);
+        }
+        universe
+    }
+
+    /// The cells of `universe` with y increasing downwards like in files, moved so
+    /// the top left of their bounding box is at the origin.
+    fn shape(cells: impl Iterator<Item = (IVec2, u8)>) -> Vec<(i32, i32, u8)> {
+        let cells = cells.collect::<Vec<_>>();
+        let min = cells
+            .iter()
+            .map(|&(pos, _)| pos)
+            .reduce(IVec2::min)
+            .unwrap_or_default();
+        let mut cells = cells
+            .into_iter()
+            .map(|(pos, state)| (pos.x - min.x, pos.y - min.y, state))
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    fn flipped(universe: &Universe) -> impl Iterator<Item = (IVec2, u8)> + '_ {
+        universe
+            .iter_states()
+            .map(|(pos, state)| (IVec2::new(pos.x, -pos.y), state))
+    }
+
+    #[test]
+    fn macrocell_round_trips() {
+        let mut hashlife = soup("B3/S23");
+        hashlife.set_engine(Engine::HashLife);
+        let universes = ["B3/S23", "B36/S23", "B2/S/C4"].map(soup);
+        for universe in universes.iter().chain([&hashlife]) {
+            let text = macrocell::write(universe);
+            let board = load_pattern(Path::new("soup.mc"), text.as_bytes()).unwrap();
+            assert_eq!(board.rule.as_ref(), Some(universe.rules()));
+            assert_eq!(board.generation, universe.generation());
+
+            // Placed where they were saved from, the way the app places them.
+            let mut loaded = Universe::new(GameRules::conway());
+            loaded.set_rules(board.rule.clone().unwrap());
+            if let Some(tree) = board.tree {
+                loaded.add_tree(tree);
+            }
+            for (pos, state) in board.cells {
+                loaded.set_state(IVec2::new(pos.x, -pos.y), state);
+            }
+            let cells = |universe: &Universe| {
+                let mut cells = universe.iter_states().collect::<Vec<_>>();
+                cells.sort_unstable_by_key(|&(pos, _)| (pos.x, pos.y));
+                cells
+            };
+            assert_eq!(cells(&loaded), cells(universe), "{}", universe.rules());
         }
+
+        let text = "[M2] (golly 4.0)\n#R Banks-I\n1 0 1 1 1\n2 0 1 0 0\n";
+        let board = load_pattern(Path::new("banks.mc"), text.as_bytes()).unwrap();
+        assert_eq!(board.rule_file.as_deref(), Some("Banks-I"));
+        assert_eq!(board.tree.map(|tree| tree.population()), Some(3));
+    }
+
+    #[test]
+    fn rle_saves_rows_that_fill() {
+        for rule in ["W1", "C1,K3,R1"] {
+            let mut universe = Universe::new(GameRules::conway());
+            universe.set_rules(rule.parse().unwrap());
+            for x in [-2, 0, 1, 5] {
+                universe.set(IVec2::new(x, 0), true);
+            }
+            let mut randomness = Randomness::new(0);
+            for _ in 0..3 {
+                universe.step(&mut randomness);
+            }
+            let mut states = universe.iter_states().collect::<Vec<_>>();
+            assert!(states.iter().all(|&(_, state)| state != 0), "{}", rule);
+            assert_eq!(universe.population(), states.len() as u64);
+
+            let text = rle::write(&universe, rule, &[]);
+            let board = rle::parse(&text).unwrap();
+            let min = states
+                .iter()
+                .map(|&(pos, _)| pos)
+                .reduce(IVec2::min)
+                .unwrap();
+            let max = states
+                .iter()
+                .map(|&(pos, _)| pos)
+                .reduce(IVec2::max)
+                .unwrap();
+            let mut read = board
+                .cells
+                .iter()
+                .map(|&(pos, state)| (IVec2::new(min.x + pos.x, max.y - pos.y), state))
+                .collect::<Vec<_>>();
+            states.sort_unstable_by_key(|&(pos, _)| (pos.x, pos.y));
+            read.sort_unstable_by_key(|&(pos, _)| (pos.x, pos.y));
+            assert_eq!(read, states, "{}", rule);
+        }
+    }
+
+    #[test]
+    fn rle_round_trips() {
+        for rule in ["B3/S23", "B36/S23", "B2/S/C4", "B3-cnqy/S23-a4itz"] {
+            let universe = soup(rule);
+            let text = rle::write(&universe, "soup", &["a comment".to_string()]);
+            let board = load_pattern(Path::new("soup.rle"), text.as_bytes()).unwrap();
+            assert_eq!(board.rule.as_ref(), Some(universe.rules()));
+            assert_eq!(board.name.as_deref(), Some("soup"));
+            assert_eq!(board.comments, ["a comment"]);
+            assert_eq!(
+                shape(board.cells.into_iter()),
+                shape(flipped(&universe)),
+                "{}",
+                rule
+            );
+        }
+    }
+
+    #[test]
+    fn cells_round_trip() {
+        let universe = soup("B3/S23");
+        let text = write_cells(&universe, None, "soup", &["a comment".to_string()]);
+        let board = load_pattern(Path::new("soup.cells"), text.as_bytes()).unwrap();
+        assert_eq!(board.name.as_deref(), Some("soup"));
+        assert_eq!(board.comments, ["a comment"]);
+        assert_eq!(shape(board.cells.into_iter()), shape(flipped(&universe)));
+    }
+
+    #[test]
+    fn life_formats_place_cells() {
+        let glider = [(0, -1), (1, 0), (-1, 1), (0, 1), (1, 1)];
+        let life_105 = "#Life 1.05\n#D A glider\n#N\n#P -1 -1\n.*\n..*\n***\n";
+        let life_106 = "#Life 1.06\n0 -1\n1 0\n-1 1\n0 1\n1 1\n";
+        for (name, text) in [("glider.lif", life_105), ("glider.life", life_106)] {
+            let board = load_pattern(Path::new(name), text.as_bytes()).unwrap();
+            assert!(board.absolute);
+            let mut cells = board.cells.clone();
+            cells.sort_unstable_by_key(|&(pos, _)| (pos.x, pos.y));
+            let mut expected = glider.map(|(x, y)| (IVec2::new(x, y), 1));
+            expected.sort_unstable_by_key(|&(pos, _)| (pos.x, pos.y));
+            assert_eq!(cells, expected, "{}", name);
+        }
+        let board = parse_life(life_105.as_bytes()).unwrap();
+        assert_eq!(board.rule, Some(GameRules::conway()));
+        assert_eq!(board.comments, ["A glider"]);
+    }
+
+    #[test]
+    fn rle_reads_like_golly() {
+        let glider = rle::parse("#N Glider\nx = 3, y = 3\nbo$2bo$3o!\ncomment").unwrap();
+        assert_eq!(glider.size, (3, 3));
+        assert_eq!(glider.rule, None);
+        assert_eq!(glider.name.as_deref(), Some("Glider"));
+        assert_eq!(shape(glider.cells.clone().into_iter()).len(), 5);
+
+        // Whitespace and line breaks can go between any of the runs.
+        let wrapped = rle::parse("x = 3, y = 3\nb o $2bo\n$ 3o\n!").unwrap();
+        assert_eq!(
+            shape(wrapped.cells.into_iter()),
+            shape(glider.cells.clone().into_iter())
+        );
+
+        let board = rle::parse("#r 23/3\nx = 3, y = 3\nbo$2bo$3o!").unwrap();
+        assert_eq!(board.rule, Some(GameRules::conway()));
+
+        // `#P` places the pattern where Life 1.05 would.
+        let placed = rle::parse("#P -1 -1\nx = 3, y = 3\nbo$2bo$3o!").unwrap();
+        let life_105 = "#Life 1.05\n#P -1 -1\n.*\n..*\n***\n";
+        let life = load_pattern(Path::new("glider.lif"), life_105.as_bytes()).unwrap();
+        assert!(placed.absolute);
+        let sorted = |mut cells: Vec<(IVec2, u8)>| {
+            cells.sort_unstable_by_key(|&(pos, _)| (pos.x, pos.y));
+            cells
+        };
+        assert_eq!(sorted(placed.cells), sorted(life.cells));
+    }
+
+    #[test]
+    fn rle_reads_states() {
+        let board = rle::parse("x = 3, y = 1, rule = B2/S/C3\nA.B!").unwrap();
+        let states = board
+            .cells
+            .iter()
+            .map(|&(_, state)| state)
+            .collect::<Vec<_>>();
+        assert_eq!(states, [1, 2]);
+        // Two state patterns can use the letters too.
+        let board = rle::parse("x = 3, y = 1\nA.A!").unwrap();
+        assert_eq!(board.cells.len(), 2);
     }
 }
@@ -0,0 +1,302 @@
+//! Golly's [bounded grids](https://golly.sourceforge.io/Help/bounded.html), a suffix
+//! like `:T100,80` on a rule that makes the universe a finite plane, a torus or a
+//! Klein bottle instead of going on forever.
+
+use std::fmt;
+
+use bevy::math::IVec2;
+use bevy::utils::HashMap;
+
+use crate::rules::RuleParseError;
+
+/// How the edges of a bounded grid are joined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topology {
+    /// `P`, the edges aren't joined and every cell past them is dead.
+    Plane,
+    /// `T`, opposite edges are joined so patterns come back round the other side.
+    Torus,
+    /// `K`, like a torus but one pair of edges is joined with a twist, so
+    /// patterns come back mirrored. The `*` after the width twists the top and
+    /// bottom edges, after the height the left and right ones.
+    KleinBottle { twisted_width: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundedGrid {
+    pub topology: Topology,
+    /// The number of columns, `0` for a grid that goes on forever sideways.
+    pub width: u32,
+    /// The number of rows, `0` for a grid that goes on forever up and down.
+    pub height: u32,
+}
+
+impl BoundedGrid {
+    /// The bottom left cell, the grid is centred on the origin like in Golly.
+    fn min(&self) -> IVec2 {
+        let (width, height) = (self.width as i32, self.height as i32);
+        IVec2::new(-(width / 2), height / 2 - height + 1)
+    }
+
+    /// The bottom left and top right cells, the ways the grid doesn't go on forever.
+    pub(crate) fn bounds(&self) -> (IVec2, IVec2) {
+        let min = self.min();
+        (
+            min,
+            min + IVec2::new(self.width as i32, self.height as i32) - 1,
+        )
+    }
+
+    /// Whether `pos` is on the grid.
+    pub fn contains(&self, pos: IVec2) -> bool {
+        let offset = pos - self.min();
+        let inside = |offset: i32, size: u32| size == 0 || (0..size as i32).contains(&offset);
+        inside(offset.x, self.width) && inside(offset.y, self.height)
+    }
+
+    /// The cell on the grid that `pos` is joined to, or `None` if it's past the
+    /// edge of a plane.
+    pub(crate) fn wrap(&self, pos: IVec2) -> Option<IVec2> {
+        if self.topology == Topology::Plane {
+            return self.contains(pos).then_some(pos);
+        }
+        let min = self.min();
+        let (width, height) = (self.width as i32, self.height as i32);
+        // How many times `pos` is across each edge, and where it lands.
+        let wrap = |offset: i32, size: i32| match size {
+            0 => (0, offset),
+            _ => (offset.div_euclid(size), offset.rem_euclid(size)),
+        };
+        let (across_x, mut x) = wrap(pos.x - min.x, width);
+        let (across_y, mut y) = wrap(pos.y - min.y, height);
+        match self.topology {
+            Topology::KleinBottle {
+                twisted_width: true,
+            } if across_y % 2 != 0 => x = width - 1 - x,
+            Topology::KleinBottle {
+                twisted_width: false,
+            } if across_x % 2 != 0 => y = height - 1 - y,
+            _ => {}
+        }
+        Some(min + IVec2::new(x, y))
+    }
+
+    /// Steps the cells of the grid with `step`, a stepper for an unbounded universe.
+    ///
+    /// The grid is surrounded by copies of the cells `reach` from each edge that
+    /// it's joined to, so cells near the edges see the neighbours across them,
+    /// and whatever is computed off the grid is thrown away.
+    pub(crate) fn step(
+        &self,
+        cells: &HashMap<IVec2, u8>,
+        reach: i32,
+        step: impl Fn(&HashMap<IVec2, u8>) -> HashMap<IVec2, u8>,
+    ) -> HashMap<IVec2, u8> {
+        let min = self.min();
+        let (width, height) = (self.width as i32, self.height as i32);
+        // Where `offset` is reflected to by a twisted edge.
+        let mirror = |offset: i32, size: i32| size - 1 - offset;
+        // How many copies of the grid `reach` goes across in each direction.
+        let copies = |size: i32| match size {
+            0 => 0,
+            _ => (reach + size - 1) / size,
+        };
+        let near = |offset: i32, size: i32| size == 0 || (-reach..size + reach).contains(&offset);
+
+        let mut padded = cells.clone();
+        if self.topology != Topology::Plane {
+            for (&pos, &state) in cells {
+                let offset = pos - min;
+                for x in [offset.x, mirror(offset.x, width)] {
+                    for y in [offset.y, mirror(offset.y, height)] {
+                        for across_y in -copies(height)..=copies(height) {
+                            for across_x in -copies(width)..=copies(width) {
+                                let copy = IVec2::new(x + across_x * width, y + across_y * height);
+                                let at = min + copy;
+                                if near(copy.x, width)
+                                    && near(copy.y, height)
+                                    && !self.contains(at)
+                                    && self.wrap(at) == Some(pos)
+                                {
+                                    padded.insert(at, state);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        step(&padded)
+            .into_iter()
+            .filter(|&(pos, _)| self.contains(pos))
+            .collect()
+    }
+
+    /// Splits a `:T100,80` style suffix off the end of a rulestring.
+    pub(crate) fn split_suffix(rule: &str) -> Result<(&str, Option<BoundedGrid>), RuleParseError> {
+        let (rule, suffix) = match rule.rsplit_once(':') {
+            Some(split) => split,
+            None => return Ok((rule, None)),
+        };
+        let invalid = || RuleParseError::InvalidGrid(suffix.to_string());
+        let mut chars = suffix.chars();
+        let kind = chars.next().map(|c| c.to_ascii_uppercase());
+        let (width, height) = match chars.as_str().split_once(',') {
+            Some(size) => size,
+            // A single size is a square grid.
+            None => (chars.as_str(), chars.as_str()),
+        };
+        let size = |size: &str| {
+            let (size, twisted) = match size.strip_suffix('*') {
+                Some(size) => (size, true),
+                None => (size, false),
+            };
+            size.trim().parse::<u32>().map(|size| (size, twisted))
+        };
+        let ((width, twisted_width), (height, twisted_height)) = match (size(width), size(height)) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(invalid()),
+        };
+        let topology = match (kind, twisted_width, twisted_height) {
+            (Some('P'), false, false) => Topology::Plane,
+            (Some('T'), false, false) => Topology::Torus,
+            (Some('K'), true, false) | (Some('K'), false, true) if width > 0 && height > 0 => {
+                Topology::KleinBottle { twisted_width }
+            }
+            _ => return Err(invalid()),
+        };
+        let grid = BoundedGrid {
+            topology,
+            width,
+            height,
+        };
+        Ok((rule, Some(grid)))
+    }
+}
+
+impl fmt::Display for BoundedGrid {
+    /// Formats the grid as the suffix of a rulestring.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, twisted_width, twisted_height) = match self.topology {
+            Topology::Plane => ('P', false, false),
+            Topology::Torus => ('T', false, false),
+            Topology::KleinBottle { twisted_width } => ('K', twisted_width, !twisted_width),
+        };
+        let twist = |twisted: bool| if twisted { "*" } else { "" };
+        write!(
+            f,
+            ":{}{}{},{}{}",
+            kind,
+            self.width,
+            twist(twisted_width),
+            self.height,
+            twist(twisted_height)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stochastic::Randomness;
+    use crate::universe::Universe;
+
+    fn sorted(universe: &Universe) -> Vec<(i32, i32)> {
+        let mut cells = universe
+            .iter_alive()
+            .map(|pos| (pos.x, pos.y))
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    /// A universe running Life on `grid` with a glider heading down and right.
+    fn glider(grid: &str) -> Universe {
+        let rules = format!("B3/S23{}", grid).parse().unwrap();
+        let mut universe = Universe::new(rules);
+        universe.set_rules(format!("B3/S23{}", grid).parse().unwrap());
+        for pos in [(0, 1), (1, 0), (-1, -1), (0, -1), (1, -1)] {
+            universe.set(IVec2::from(pos), true);
+        }
+        universe
+    }
+
+    #[test]
+    fn gliders_wrap_round_a_torus() {
+        let mut universe = glider(":T16,12");
+        let start = sorted(&universe);
+        let grid = universe.rules().grid().unwrap().clone();
+        let mut randomness = Randomness::new(0);
+        // A glider moves a cell every four generations, so it goes round both
+        // ways in 4 * 48 generations.
+        for generation in 1..=4 * 48 {
+            universe.step(&mut randomness);
+            assert_eq!(universe.population(), 5, "{}", generation);
+            assert!(universe.iter_alive().all(|pos| grid.contains(pos)));
+            let back = generation % (4 * 16) == 0 && generation % (4 * 12) == 0;
+            assert_eq!(sorted(&universe) == start, back, "{}", generation);
+        }
+    }
+
+    #[test]
+    fn gliders_stop_at_the_edge_of_a_plane() {
+        let mut universe = glider(":P16,12");
+        let grid = universe.rules().grid().unwrap().clone();
+        let mut randomness = Randomness::new(0);
+        for _ in 0..100 {
+            universe.step(&mut randomness);
+            assert!(universe.iter_alive().all(|pos| grid.contains(pos)));
+        }
+        assert_ne!(universe.population(), 5);
+    }
+
+    #[test]
+    fn edges_join() {
+        let (_, grid) = BoundedGrid::split_suffix("B3/S23:K10*,8").unwrap();
+        let grid = grid.unwrap();
+        let (min, max) = grid.bounds();
+        assert_eq!((min, max), (IVec2::new(-5, -3), IVec2::new(4, 4)));
+        // Across the top and bottom the grid is mirrored, across the sides it isn't.
+        assert_eq!(grid.wrap(IVec2::new(-5, 5)), Some(IVec2::new(4, -3)));
+        assert_eq!(grid.wrap(IVec2::new(5, 0)), Some(IVec2::new(-5, 0)));
+        assert_eq!(grid.wrap(IVec2::new(0, 0)), Some(IVec2::ZERO));
+
+        let (_, grid) = BoundedGrid::split_suffix("B3/S23:P10,0").unwrap();
+        let grid = grid.unwrap();
+        assert_eq!(
+            grid.wrap(IVec2::new(0, 1_000_000)),
+            Some(IVec2::new(0, 1_000_000))
+        );
+        assert_eq!(grid.wrap(IVec2::new(5, 0)), None);
+    }
+
+    #[test]
+    fn suffixes() {
+        for suffix in [":T100,80", ":P50,50", ":K100*,80", ":K100,80*", ":T0,30"] {
+            let rule = format!("B3/S23{}", suffix);
+            let (rule, grid) = BoundedGrid::split_suffix(&rule).unwrap();
+            assert_eq!(rule, "B3/S23");
+            assert_eq!(grid.unwrap().to_string(), suffix);
+        }
+        assert_eq!(
+            BoundedGrid::split_suffix("B3/S23:t20").unwrap().1.unwrap(),
+            BoundedGrid {
+                topology: Topology::Torus,
+                width: 20,
+                height: 20,
+            }
+        );
+        assert_eq!(
+            BoundedGrid::split_suffix("B3/S23").unwrap(),
+            ("B3/S23", None)
+        );
+        for suffix in [":K100,80", ":K100*,80*", ":X10,10", ":T10,a", ":T*10,10"] {
+            assert!(
+                BoundedGrid::split_suffix(&format!("B3/S23{}", suffix)).is_err(),
+                "{}",
+                suffix
+            );
+        }
+    }
+}
@@ -0,0 +1,559 @@
+//! Continuous automata, where each cell is a real number between 0 and 1 rather
+//! than one of a few states: [Lenia](https://en.wikipedia.org/wiki/Lenia), written
+//! `Lenia,R13,T10,M0.15,S0.015,B1`, and [SmoothLife](https://arxiv.org/abs/1111.1567),
+//! written `SmoothLife,R21,B0.278..0.365,D0.267..0.445,N0.028,M0.147,T1`.
+//!
+//! Cells are kept in a dense field on a bounded grid, and the weighted sums of
+//! their neighbourhoods are convolutions done with a fast Fourier transform, so
+//! a big kernel costs no more than a small one.
+
+use std::fmt;
+
+use bevy::math::IVec2;
+
+use crate::bounded::{BoundedGrid, Topology};
+use crate::fft::{self, Complex};
+use crate::rules::RuleParseError;
+
+/// How many states a continuous cell is rounded to when it's read as a state, so
+/// patterns can be drawn and saved like any other multi-state pattern.
+pub const LEVELS: u8 = 255;
+
+/// The grid continuous rules run on when they aren't given one.
+pub static DEFAULT_GRID: BoundedGrid = BoundedGrid {
+    topology: Topology::Torus,
+    width: 128,
+    height: 128,
+};
+
+/// The most cells a grid can have for a continuous rule.
+pub const MAX_AREA: u64 = 1 << 22;
+
+/// The biggest radius a kernel can have.
+const MAX_RADIUS: u32 = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContinuousRule {
+    /// Cells grow or shrink by how close a weighted sum of the rings around them
+    /// is to `mu`.
+    Lenia {
+        radius: u32,
+        /// How many steps a unit of time is split into, each one moves cells a
+        /// `1 / time_steps` of the way.
+        time_steps: u32,
+        /// The sum cells grow fastest at.
+        mu: f32,
+        /// How far from `mu` the sum can be before cells shrink instead.
+        sigma: f32,
+        /// The height of each ring of the kernel, from the innermost.
+        peaks: Vec<f32>,
+    },
+    /// Life with the neighbours counted as the fill of a ring around a disk,
+    /// born or surviving when it's within `birth` or `death`.
+    SmoothLife {
+        /// The outer radius of the ring, the disk is a third of it.
+        radius: u32,
+        birth: (f32, f32),
+        death: (f32, f32),
+        /// How smoothly the ring's fill moves between being born and not.
+        alpha_n: f32,
+        /// How smoothly the disk's fill moves between counting as alive and dead.
+        alpha_m: f32,
+        /// `1` replaces cells each step, more moves them part of the way there.
+        time_steps: u32,
+    },
+}
+
+// None of the numbers are ever NaN.
+impl Eq for ContinuousRule {}
+
+impl ContinuousRule {
+    /// Bert Chan's Orbium, a glider.
+    pub fn lenia() -> Self {
+        ContinuousRule::Lenia {
+            radius: 13,
+            time_steps: 10,
+            mu: 0.15,
+            sigma: 0.015,
+            peaks: vec![1.],
+        }
+    }
+
+    /// Stephan Rafler's rule from the SmoothLife paper.
+    pub fn smooth_life() -> Self {
+        ContinuousRule::SmoothLife {
+            radius: 21,
+            birth: (0.278, 0.365),
+            death: (0.267, 0.445),
+            alpha_n: 0.028,
+            alpha_m: 0.147,
+            time_steps: 1,
+        }
+    }
+
+    /// How far away the cells that decide the next value of a cell can be.
+    fn reach(&self) -> i32 {
+        match self {
+            ContinuousRule::Lenia { radius, .. } => *radius as i32,
+            // The ring's edge is smoothed over the next cell out.
+            ContinuousRule::SmoothLife { radius, .. } => *radius as i32 + 1,
+        }
+    }
+
+    /// The kernels the field is convolved with, each a weight for every offset in
+    /// the square `reach` either side of a cell, row by row. Each adds up to 1.
+    fn kernels(&self) -> Vec<Vec<f32>> {
+        let reach = self.reach();
+        let distances = (-reach..=reach)
+            .flat_map(|dy| (-reach..=reach).map(move |dx| ((dx * dx + dy * dy) as f32).sqrt()))
+            .collect::<Vec<_>>();
+        let kernels = match self {
+            ContinuousRule::Lenia { radius, peaks, .. } => {
+                // Each ring is a smooth bump that's 0 at its edges.
+                let bump = |x: f32| match x {
+                    x if x > 0. && x < 1. => (4. - 1. / (x * (1. - x))).exp(),
+                    _ => 0.,
+                };
+                let ring = |r: f32| {
+                    let r = r / *radius as f32 * peaks.len() as f32;
+                    peaks
+                        .get(r as usize)
+                        .map_or(0., |peak| peak * bump(r.fract()))
+                };
+                vec![distances.iter().map(|&r| ring(r)).collect::<Vec<_>>()]
+            }
+            ContinuousRule::SmoothLife { radius, .. } => {
+                // Cells on the edge of a circle count for how much of them is inside.
+                let inside = |r: f32, radius: f32| (radius + 0.5 - r).clamp(0., 1.);
+                let (outer, inner) = (*radius as f32, *radius as f32 / 3.);
+                let disk = distances.iter().map(|&r| inside(r, inner)).collect();
+                let ring = distances
+                    .iter()
+                    .map(|&r| inside(r, outer) - inside(r, inner))
+                    .collect();
+                vec![ring, disk]
+            }
+        };
+        kernels
+            .into_iter()
+            .map(|kernel: Vec<f32>| {
+                let total = kernel.iter().sum::<f32>();
+                kernel.into_iter().map(|weight| weight / total).collect()
+            })
+            .collect()
+    }
+
+    /// The value a cell at `value` moves to, given the convolutions of each
+    /// kernel at it.
+    fn next_value(&self, value: f32, sums: &[f32]) -> f32 {
+        let next = match self {
+            ContinuousRule::Lenia {
+                time_steps,
+                mu,
+                sigma,
+                ..
+            } => {
+                let growth = 2. * (-(sums[0] - mu).powi(2) / (2. * sigma * sigma)).exp() - 1.;
+                value + growth / *time_steps as f32
+            }
+            ContinuousRule::SmoothLife {
+                birth,
+                death,
+                alpha_n,
+                alpha_m,
+                time_steps,
+                ..
+            } => {
+                let (n, m) = (sums[0], sums[1]);
+                let step =
+                    |x: f32, edge: f32, alpha: f32| 1. / (1. + (-(x - edge) * 4. / alpha).exp());
+                let between = |x: f32, low: f32, high: f32| {
+                    step(x, low, *alpha_n) * (1. - step(x, high, *alpha_n))
+                };
+                // How alive the cell is decides whether it's born or survives.
+                let alive = step(m, 0.5, *alpha_m);
+                let mix = |born: f32, survives: f32| born * (1. - alive) + survives * alive;
+                let s = between(n, mix(birth.0, death.0), mix(birth.1, death.1));
+                match time_steps {
+                    1 => s,
+                    _ => value + (2. * s - 1.) / *time_steps as f32,
+                }
+            }
+        };
+        next.clamp(0., 1.)
+    }
+
+    /// Whether `rule` is named as one of the continuous rules.
+    pub(crate) fn matches(rule: &str) -> bool {
+        let name = rule.split(',').next().unwrap_or_default().trim();
+        name.eq_ignore_ascii_case("Lenia") || name.eq_ignore_ascii_case("SmoothLife")
+    }
+}
+
+impl fmt::Display for ContinuousRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ContinuousRule::Lenia {
+                radius,
+                time_steps,
+                mu,
+                sigma,
+                peaks,
+            } => {
+                let peaks = peaks.iter().map(f32::to_string).collect::<Vec<_>>();
+                write!(
+                    f,
+                    "Lenia,R{},T{},M{},S{},B{}",
+                    radius,
+                    time_steps,
+                    mu,
+                    sigma,
+                    peaks.join("/")
+                )
+            }
+            ContinuousRule::SmoothLife {
+                radius,
+                birth,
+                death,
+                alpha_n,
+                alpha_m,
+                time_steps,
+            } => write!(
+                f,
+                "SmoothLife,R{},B{}..{},D{}..{},N{},M{},T{}",
+                radius, birth.0, birth.1, death.0, death.1, alpha_n, alpha_m, time_steps
+            ),
+        }
+    }
+}
+
+impl std::str::FromStr for ContinuousRule {
+    type Err = RuleParseError;
+
+    /// Parses the name and then any parameters to change from the defaults, each
+    /// a letter and a value, separated by commas.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',').map(str::trim);
+        let mut rule = match parts.next() {
+            Some(name) if name.eq_ignore_ascii_case("Lenia") => ContinuousRule::lenia(),
+            Some(name) if name.eq_ignore_ascii_case("SmoothLife") => ContinuousRule::smooth_life(),
+            _ => return Err(RuleParseError::Malformed),
+        };
+        for part in parts {
+            let invalid = || RuleParseError::InvalidParameter(part.to_string());
+            let mut chars = part.chars();
+            let key = chars.next().map(|c| c.to_ascii_uppercase());
+            let value = chars.as_str();
+            let number = |min: f32| match value.parse::<f32>() {
+                Ok(number) if number >= min && number.is_finite() => Ok(number),
+                _ => Err(invalid()),
+            };
+            let whole = |min: u32, max: u32| match value.parse() {
+                Ok(number) if (min..=max).contains(&number) => Ok(number),
+                _ => Err(invalid()),
+            };
+            let range = || match value.split_once("..") {
+                Some((low, high)) => match (low.parse::<f32>(), high.parse::<f32>()) {
+                    (Ok(low), Ok(high)) if (0. ..=high).contains(&low) && high <= 1. => {
+                        Ok((low, high))
+                    }
+                    _ => Err(invalid()),
+                },
+                None => Err(invalid()),
+            };
+            match (&mut rule, key) {
+                (ContinuousRule::Lenia { radius, .. }, Some('R')) => {
+                    *radius = whole(1, MAX_RADIUS)?
+                }
+                (ContinuousRule::Lenia { time_steps, .. }, Some('T')) => {
+                    *time_steps = whole(1, u32::MAX)?
+                }
+                (ContinuousRule::Lenia { mu, .. }, Some('M')) => *mu = number(0.)?,
+                (ContinuousRule::Lenia { sigma, .. }, Some('S')) => *sigma = number(f32::EPSILON)?,
+                (ContinuousRule::Lenia { peaks, .. }, Some('B')) => {
+                    *peaks = value
+                        .split('/')
+                        .map(|peak| match peak.parse::<f32>() {
+                            Ok(peak) if (0. ..=1.).contains(&peak) => Ok(peak),
+                            _ => Err(invalid()),
+                        })
+                        .collect::<Result<_, _>>()?;
+                    // The kernel is divided by its total, which mustn't be 0.
+                    if peaks.iter().all(|&peak| peak == 0.) {
+                        return Err(invalid());
+                    }
+                }
+                (ContinuousRule::SmoothLife { radius, .. }, Some('R')) => {
+                    *radius = whole(3, MAX_RADIUS)?
+                }
+                (ContinuousRule::SmoothLife { birth, .. }, Some('B')) => *birth = range()?,
+                (ContinuousRule::SmoothLife { death, .. }, Some('D')) => *death = range()?,
+                (ContinuousRule::SmoothLife { alpha_n, .. }, Some('N')) => {
+                    *alpha_n = number(f32::EPSILON)?
+                }
+                (ContinuousRule::SmoothLife { alpha_m, .. }, Some('M')) => {
+                    *alpha_m = number(f32::EPSILON)?
+                }
+                (ContinuousRule::SmoothLife { time_steps, .. }, Some('T')) => {
+                    *time_steps = whole(1, u32::MAX)?
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(rule)
+    }
+}
+
+/// The value of every cell on a bounded grid.
+#[derive(Debug, Clone)]
+pub(crate) struct Field {
+    grid: BoundedGrid,
+    /// Row by row from the bottom left of the grid.
+    values: Vec<f32>,
+    /// The Fourier transforms of the kernels of the last rule the field was
+    /// stepped with, which only have to be made again when it changes.
+    spectra: Option<(ContinuousRule, Vec<Vec<Complex>>)>,
+}
+
+impl Field {
+    /// A field of dead cells covering `grid`, which mustn't go on forever.
+    pub fn new(grid: &BoundedGrid) -> Self {
+        Self {
+            grid: grid.clone(),
+            values: vec![0.; grid.width as usize * grid.height as usize],
+            spectra: None,
+        }
+    }
+
+    fn index(&self, pos: IVec2) -> Option<usize> {
+        if !self.grid.contains(pos) {
+            return None;
+        }
+        let offset = pos - self.grid.bounds().0;
+        Some(offset.y as usize * self.grid.width as usize + offset.x as usize)
+    }
+
+    /// The value of the cell at `pos`, `0` off the grid.
+    pub fn get(&self, pos: IVec2) -> f32 {
+        self.index(pos).map_or(0., |i| self.values[i])
+    }
+
+    /// Sets the value of the cell at `pos`, if it's on the grid.
+    pub fn set(&mut self, pos: IVec2, value: f32) {
+        if let Some(i) = self.index(pos) {
+            self.values[i] = value.clamp(0., 1.);
+        }
+    }
+
+    /// Every cell of the grid, along with its value.
+    pub fn iter(&self) -> impl Iterator<Item = (IVec2, f32)> + '_ {
+        let (min, width) = (self.grid.bounds().0, self.grid.width as usize);
+        self.values.iter().enumerate().map(move |(i, &value)| {
+            let offset = IVec2::new((i % width) as i32, (i / width) as i32);
+            (min + offset, value)
+        })
+    }
+
+    /// The size the field is transformed at, big enough to fit the grid with
+    /// `reach` cells either side so convolutions don't wrap round onto it.
+    fn padded_size(&self, reach: i32) -> (usize, usize) {
+        let padded = |size: u32| (size as usize + 2 * reach as usize).next_power_of_two();
+        (padded(self.grid.width), padded(self.grid.height))
+    }
+
+    /// Moves every cell on to its next value.
+    pub fn step(&mut self, rule: &ContinuousRule) {
+        let reach = self.reach_for(rule);
+        let (width, height) = (self.grid.width as usize, self.grid.height as usize);
+        let (padded_width, padded_height) = self.padded_size(reach);
+
+        // The grid with the cells `reach` past each edge, which are dead past the
+        // edges of a plane and the cells they're joined to otherwise.
+        let mut transform = vec![Complex::default(); padded_width * padded_height];
+        let min = self.grid.bounds().0 - reach;
+        let padding = 2 * reach as usize;
+        for y in 0..height + padding {
+            for x in 0..width + padding {
+                let pos = min + IVec2::new(x as i32, y as i32);
+                if let Some(pos) = self.grid.wrap(pos) {
+                    transform[y * padded_width + x].re = self.get(pos);
+                }
+            }
+        }
+        fft::fft_2d(&mut transform, padded_width, padded_height, false);
+
+        let spectra = &self.spectra.as_ref().unwrap().1;
+        let sums = spectra
+            .iter()
+            .map(|spectrum| {
+                let mut sums = transform
+                    .iter()
+                    .zip(spectrum)
+                    .map(|(&a, &b)| a * b)
+                    .collect::<Vec<_>>();
+                fft::fft_2d(&mut sums, padded_width, padded_height, true);
+                sums
+            })
+            .collect::<Vec<_>>();
+
+        let reach = reach as usize;
+        let mut cell_sums = Vec::with_capacity(sums.len());
+        for (i, value) in self.values.iter_mut().enumerate() {
+            let (x, y) = (i % width + reach, i / width + reach);
+            cell_sums.clear();
+            cell_sums.extend(sums.iter().map(|sums| sums[y * padded_width + x].re));
+            *value = rule.next_value(*value, &cell_sums);
+        }
+    }
+
+    /// The reach of `rule`, making the transforms of its kernels first if the
+    /// field hasn't been stepped with it before.
+    fn reach_for(&mut self, rule: &ContinuousRule) -> i32 {
+        let reach = rule.reach();
+        if self.spectra.as_ref().is_some_and(|(last, _)| last == rule) {
+            return reach;
+        }
+        let (padded_width, padded_height) = self.padded_size(reach);
+        let side = 2 * reach as usize + 1;
+        let spectra = rule
+            .kernels()
+            .into_iter()
+            .map(|kernel| {
+                // Put each offset where it wraps round to, so the sum for a cell
+                // ends up where the cell is.
+                let mut spectrum = vec![Complex::default(); padded_width * padded_height];
+                for (i, weight) in kernel.into_iter().enumerate() {
+                    let dx = (i % side) as i32 - reach;
+                    let dy = (i / side) as i32 - reach;
+                    let x = dx.rem_euclid(padded_width as i32) as usize;
+                    let y = dy.rem_euclid(padded_height as i32) as usize;
+                    spectrum[y * padded_width + x].re = weight;
+                }
+                fft::fft_2d(&mut spectrum, padded_width, padded_height, false);
+                spectrum
+            })
+            .collect();
+        self.spectra = Some((rule.clone(), spectra));
+        reach
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::rules::GameRules;
+    use crate::universe::{Engine, Universe};
+
+    /// A random field on `grid`.
+    fn soup(grid: &str) -> Field {
+        let (_, grid) = BoundedGrid::split_suffix(grid).unwrap();
+        let mut field = Field::new(&grid.unwrap());
+        let mut rng = StdRng::seed_from_u64(4);
+        let cells = field.iter().map(|(pos, _)| pos).collect::<Vec<_>>();
+        for pos in cells {
+            field.set(pos, rng.gen());
+        }
+        field
+    }
+
+    /// The next value of every cell, summing the kernels cell by cell.
+    fn naive_step(field: &Field, rule: &ContinuousRule) -> Vec<f32> {
+        let reach = rule.reach();
+        let kernels = rule.kernels();
+        let side = 2 * reach + 1;
+        field
+            .iter()
+            .map(|(pos, value)| {
+                let sums = kernels
+                    .iter()
+                    .map(|kernel| {
+                        let weighted = kernel.iter().enumerate().map(|(i, weight)| {
+                            let offset = IVec2::new(i as i32 % side, i as i32 / side) - reach;
+                            let neighbour = field.grid.wrap(pos + offset);
+                            neighbour.map_or(0., |pos| weight * field.get(pos))
+                        });
+                        weighted.sum()
+                    })
+                    .collect::<Vec<_>>();
+                rule.next_value(value, &sums)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn agrees_with_summing() {
+        for (rule, grid) in [
+            ("Lenia,R5,T2", ":T20,12"),
+            ("Lenia,R4,B1/0.5", ":P16,16"),
+            ("SmoothLife,R6", ":K18*,10"),
+        ] {
+            let rule = rule.parse::<ContinuousRule>().unwrap();
+            let mut field = soup(grid);
+            let expected = naive_step(&field, &rule);
+            field.step(&rule);
+            let values = field.iter().map(|(_, value)| value);
+            assert!(
+                values.zip(expected).all(|(a, b)| (a - b).abs() < 1e-3),
+                "{}{}",
+                rule,
+                grid
+            );
+        }
+    }
+
+    #[test]
+    fn kernels_add_up_to_one() {
+        for rule in [ContinuousRule::lenia(), ContinuousRule::smooth_life()] {
+            for kernel in rule.kernels() {
+                assert!((kernel.iter().sum::<f32>() - 1.).abs() < 1e-4, "{}", rule);
+            }
+        }
+    }
+
+    #[test]
+    fn states_map_to_values() {
+        let mut universe = Universe::new(GameRules::conway());
+        universe.set_rules("B2/S/C3".parse().unwrap());
+        universe.set_state(IVec2::new(1, 2), 1);
+        universe.set_state(IVec2::new(3, 3), 2);
+        universe.set_rules("Lenia".parse().unwrap());
+        assert_eq!(universe.engine(), Engine::Continuous);
+        // Alive cells are full and dying ones fade out.
+        assert_eq!(universe.value(IVec2::new(1, 2)), 1.);
+        assert_eq!(universe.value(IVec2::new(3, 3)), 0.5);
+        universe.set_rules("B2/S/C5".parse().unwrap());
+        assert_eq!(universe.state(IVec2::new(1, 2)), 1);
+        assert_eq!(universe.state(IVec2::new(3, 3)), 3);
+        assert_eq!(universe.population(), 2);
+    }
+
+    #[test]
+    fn parameters() {
+        let rule = "lenia, R20, M0.2, B1/0.5/0"
+            .parse::<ContinuousRule>()
+            .unwrap();
+        assert_eq!(rule.to_string(), "Lenia,R20,T10,M0.2,S0.015,B1/0.5/0");
+        for rule in [
+            "Lenia,B0",
+            "Lenia,B0/0",
+            "Lenia,R0",
+            "Lenia,R65",
+            "Lenia,S0",
+            "Lenia,X1",
+            "SmoothLife,R2",
+            "SmoothLife,B0.4..0.3",
+            "SmoothLife,T0",
+            "Smooth",
+        ] {
+            assert!(rule.parse::<ContinuousRule>().is_err(), "{}", rule);
+        }
+        assert_eq!(
+            "Lenia:T100000,100000".parse::<GameRules>(),
+            Err(RuleParseError::InvalidGrid("T100000,100000".to_string()))
+        );
+        assert!("Lenia:T0,100".parse::<GameRules>().is_err());
+    }
+}
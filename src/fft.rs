@@ -0,0 +1,170 @@
+//! A radix-2 fast Fourier transform, for convolving fields with big kernels.
+
+use std::ops::{Add, Mul, Sub};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct Complex {
+    pub re: f32,
+    pub im: f32,
+}
+
+impl Complex {
+    pub fn new(re: f32, im: f32) -> Self {
+        Self { re, im }
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, rhs: Complex) -> Complex {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+/// `e^(-2πik/len)` for every `k` below `len / 2`, the roots of unity a transform
+/// of `len` values multiplies by. They're worked out in `f64` so they stay accurate.
+fn twiddles(len: usize) -> Vec<Complex> {
+    (0..len / 2)
+        .map(|k| {
+            let angle = -std::f64::consts::TAU * k as f64 / len as f64;
+            Complex::new(angle.cos() as f32, angle.sin() as f32)
+        })
+        .collect()
+}
+
+/// Transforms `data` in place, whose length has to be a power of two. The inverse
+/// transform is scaled so it undoes the forward one.
+fn fft(data: &mut [Complex], twiddles: &[Complex], inverse: bool) {
+    let len = data.len();
+    debug_assert!(len.is_power_of_two());
+    let bits = len.trailing_zeros();
+    if bits == 0 {
+        return;
+    }
+    for i in 0..len {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let mut size = 2;
+    while size <= len {
+        let stride = len / size;
+        for start in (0..len).step_by(size) {
+            for k in 0..size / 2 {
+                let mut twiddle = twiddles[k * stride];
+                if inverse {
+                    twiddle.im = -twiddle.im;
+                }
+                let (even, odd) = (start + k, start + k + size / 2);
+                let product = data[odd] * twiddle;
+                data[odd] = data[even] - product;
+                data[even] = data[even] + product;
+            }
+        }
+        size *= 2;
+    }
+
+    if inverse {
+        let scale = 1. / len as f32;
+        for value in data {
+            value.re *= scale;
+            value.im *= scale;
+        }
+    }
+}
+
+/// Transforms the `width` by `height` grid `data`, stored row by row, in place.
+/// Both sides have to be powers of two.
+pub(crate) fn fft_2d(data: &mut [Complex], width: usize, height: usize, inverse: bool) {
+    let row_twiddles = twiddles(width);
+    for row in data.chunks_exact_mut(width) {
+        fft(row, &row_twiddles, inverse);
+    }
+    let column_twiddles = twiddles(height);
+    let mut column = vec![Complex::default(); height];
+    for x in 0..width {
+        for (y, value) in column.iter_mut().enumerate() {
+            *value = data[y * width + x];
+        }
+        fft(&mut column, &column_twiddles, inverse);
+        for (y, value) in column.iter().enumerate() {
+            data[y * width + x] = *value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    fn random(len: usize) -> Vec<Complex> {
+        let mut rng = StdRng::seed_from_u64(3);
+        (0..len)
+            .map(|_| Complex::new(rng.gen_range(-1. ..1.), rng.gen_range(-1. ..1.)))
+            .collect()
+    }
+
+    fn close(a: &[Complex], b: &[Complex]) -> bool {
+        a.iter()
+            .zip(b)
+            .all(|(a, b)| (a.re - b.re).abs() < 1e-4 && (a.im - b.im).abs() < 1e-4)
+    }
+
+    #[test]
+    fn inverse_undoes_forward() {
+        for (width, height) in [(1, 1), (2, 8), (16, 4), (32, 32)] {
+            let data = random(width * height);
+            let mut transform = data.clone();
+            fft_2d(&mut transform, width, height, false);
+            fft_2d(&mut transform, width, height, true);
+            assert!(close(&transform, &data), "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn matches_the_definition() {
+        let (width, height) = (8, 4);
+        let data = random(width * height);
+        let mut transform = data.clone();
+        fft_2d(&mut transform, width, height, false);
+        let expected = (0..width * height)
+            .map(|k| {
+                let (u, v) = ((k % width) as f64, (k / width) as f64);
+                data.iter()
+                    .enumerate()
+                    .fold(Complex::default(), |sum, (i, &value)| {
+                        let (x, y) = ((i % width) as f64, (i / width) as f64);
+                        let angle =
+                            -std::f64::consts::TAU * (u * x / width as f64 + v * y / height as f64);
+                        sum + value * Complex::new(angle.cos() as f32, angle.sin() as f32)
+                    })
+            })
+            .collect::<Vec<_>>();
+        assert!(close(&transform, &expected));
+    }
+}
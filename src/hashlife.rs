@@ -0,0 +1,577 @@
+use bevy::math::IVec2;
+use bevy::utils::HashMap;
+
+pub(crate) type NodeId = u32;
+
+const DEAD: NodeId = 0;
+const ALIVE: NodeId = 1;
+
+/// The smallest level the root is kept at, so it always has grandchildren.
+const MIN_ROOT_LEVEL: u8 = 3;
+
+/// How many nodes can be allocated before unreachable ones are thrown away.
+const GC_THRESHOLD: usize = 1 << 22;
+
+/// A square of `2^level` cells, split into four quadrants of the level below.
+///
+/// Level 0 nodes are single cells, and are always `DEAD` or `ALIVE`.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    /// The north-west, north-east, south-west and south-east quadrants, where
+    /// north is towards -y and west is towards -x.
+    children: [NodeId; 4],
+    level: u8,
+    population: u64,
+}
+
+/// A memoized quadtree universe that can jump ahead `2^j` generations at a time.
+///
+/// Identical squares are only ever stored once, and the result of advancing each
+/// one is cached, so repetitive patterns can be run for enormous numbers of
+/// generations. The root is always centered on the origin.
+#[derive(Debug, Clone)]
+pub struct HashLife {
+    nodes: Vec<Node>,
+    interned: HashMap<[NodeId; 4], NodeId>,
+    /// The center of a node after `2^j` generations, keyed by the node and `j`.
+    results: HashMap<(NodeId, u8), NodeId>,
+    /// The empty node of each level.
+    empty: Vec<NodeId>,
+    root: NodeId,
+    birth: u16,
+    survive: u16,
+}
+
+impl HashLife {
+    /// Creates an empty universe, `birth` and `survive` have bit `n` set if a cell
+    /// with `n` alive neighbours is born or survives.
+    pub fn new(birth: u16, survive: u16) -> Self {
+        let leaf = |population| Node {
+            children: [DEAD; 4],
+            level: 0,
+            population,
+        };
+        let mut life = Self {
+            nodes: vec![leaf(0), leaf(1)],
+            interned: HashMap::default(),
+            results: HashMap::default(),
+            empty: vec![DEAD],
+            root: DEAD,
+            birth,
+            survive,
+        };
+        life.root = life.empty(MIN_ROOT_LEVEL);
+        life
+    }
+
+    /// Changes the rule, forgetting every cached result.
+    pub fn set_rule(&mut self, birth: u16, survive: u16) {
+        self.birth = birth;
+        self.survive = survive;
+        self.results.clear();
+    }
+
+    pub fn population(&self) -> u64 {
+        self.nodes[self.root as usize].population
+    }
+
+    pub fn get(&self, pos: IVec2) -> bool {
+        let (x, y) = (pos.x as i64, pos.y as i64);
+        let half = self.half_width();
+        if x < -half || y < -half || x >= half || y >= half {
+            return false;
+        }
+        let (mut node, mut ox, mut oy) = (self.root, -half, -half);
+        while self.level(node) > 0 {
+            let half = 1i64 << (self.level(node) - 1);
+            let east = x >= ox + half;
+            let south = y >= oy + half;
+            if east {
+                ox += half;
+            }
+            if south {
+                oy += half;
+            }
+            node = self.children(node)[east as usize + 2 * south as usize];
+        }
+        node == ALIVE
+    }
+
+    pub fn set(&mut self, pos: IVec2, alive: bool) {
+        let (x, y) = (pos.x as i64, pos.y as i64);
+        while {
+            let half = self.half_width();
+            x < -half || y < -half || x >= half || y >= half
+        } {
+            self.root = self.expand(self.root);
+        }
+        let half = self.half_width();
+        self.root = self.set_in(self.root, -half, -half, x, y, alive);
+    }
+
+    /// Every alive cell, in no particular order.
+    pub fn iter_alive(&self) -> impl Iterator<Item = IVec2> + '_ {
+        // The population can be far more cells than fit in memory after a big jump,
+        // so the tree is walked as the cells are needed.
+        let half = self.half_width();
+        AliveCells {
+            life: self,
+            stack: vec![(self.root, -half, -half)],
+        }
+    }
+
+    /// The node of a `2^level` square whose cell `(x, y)` from its north-west
+    /// corner is alive if `alive(x, y)`, for building a tree from the bottom up.
+    pub(crate) fn square(&mut self, level: u8, alive: &impl Fn(i64, i64) -> bool) -> NodeId {
+        self.square_at(level, 0, 0, alive)
+    }
+
+    fn square_at(
+        &mut self,
+        level: u8,
+        ox: i64,
+        oy: i64,
+        alive: &impl Fn(i64, i64) -> bool,
+    ) -> NodeId {
+        if level == 0 {
+            return if alive(ox, oy) { ALIVE } else { DEAD };
+        }
+        let half = 1i64 << (level - 1);
+        let children = [(0, 0), (half, 0), (0, half), (half, half)]
+            .map(|(dx, dy)| self.square_at(level - 1, ox + dx, oy + dy, alive));
+        self.join(children)
+    }
+
+    /// Makes `node` the root, centered on the origin like every root is.
+    pub(crate) fn set_root(&mut self, node: NodeId) {
+        self.root = node;
+        while self.level(self.root) < MIN_ROOT_LEVEL {
+            self.root = self.expand(self.root);
+        }
+        while self.level(self.root) > MIN_ROOT_LEVEL && self.is_padded(self.root) {
+            self.root = self.center(self.root);
+        }
+    }
+
+    /// The level of the root, which covers `-2^(level - 1)` to `2^(level - 1) - 1`
+    /// either way.
+    pub(crate) fn root_level(&self) -> u8 {
+        self.level(self.root)
+    }
+
+    /// Advances the universe by `2^j` generations.
+    pub fn advance(&mut self, j: u8) {
+        // Make sure the pattern is surrounded by enough empty space that it can't
+        // grow past the edge of the result in `2^j` generations.
+        while self.level(self.root) < j + 2 || !self.is_padded(self.root) {
+            self.root = self.expand(self.root);
+        }
+        let expanded = self.expand(self.root);
+        self.root = self.successor(expanded, j);
+
+        while self.level(self.root) > MIN_ROOT_LEVEL && self.is_padded(self.root) {
+            self.root = self.center(self.root);
+        }
+
+        if self.nodes.len() > GC_THRESHOLD {
+            self.collect_garbage();
+        }
+    }
+
+    /// Writes the node lines of a Macrocell file for the tree under the root, each
+    /// node after the ones it refers to and the root last. Macrocell has y
+    /// increasing downwards, so every node is flipped over, the root covers the
+    /// same cells either way up.
+    pub(crate) fn write_macrocell_nodes(&self, out: &mut String) {
+        let mut numbers = HashMap::default();
+        let mut next = 1;
+        self.write_macrocell_node(self.root, &mut numbers, &mut next, out);
+    }
+
+    fn write_macrocell_node(
+        &self,
+        node: NodeId,
+        numbers: &mut HashMap<NodeId, usize>,
+        next: &mut usize,
+        out: &mut String,
+    ) -> usize {
+        let Node {
+            children,
+            level,
+            population,
+        } = self.nodes[node as usize];
+        if population == 0 {
+            return 0;
+        }
+        if let Some(&number) = numbers.get(&node) {
+            return number;
+        }
+
+        if level == 3 {
+            let rows = (0..8)
+                .map(|y| {
+                    let row = (0..8)
+                        .map(|x| {
+                            if self.cell_in(node, x, 7 - y) {
+                                '*'
+                            } else {
+                                '.'
+                            }
+                        })
+                        .collect::<String>();
+                    row.trim_end_matches('.').to_string()
+                })
+                .collect::<Vec<_>>();
+            let rows = &rows[..=rows.iter().rposition(|r| !r.is_empty()).unwrap()];
+            for row in rows {
+                out.push_str(row);
+                out.push('$');
+            }
+            out.push('\n');
+        } else {
+            let [nw, ne, sw, se] = children;
+            let children =
+                [sw, se, nw, ne].map(|c| self.write_macrocell_node(c, numbers, next, out));
+            out.push_str(&format!(
+                "{} {} {} {} {}\n",
+                level, children[0], children[1], children[2], children[3]
+            ));
+        }
+
+        let number = *next;
+        *next += 1;
+        numbers.insert(node, number);
+        number
+    }
+
+    /// Whether the cell at `(x, y)` from the north-west corner of `node` is alive.
+    fn cell_in(&self, mut node: NodeId, x: i64, y: i64) -> bool {
+        while self.level(node) > 0 {
+            let half = 1i64 << (self.level(node) - 1);
+            let quadrant = (x & half != 0) as usize + 2 * (y & half != 0) as usize;
+            node = self.children(node)[quadrant];
+        }
+        node == ALIVE
+    }
+
+    fn half_width(&self) -> i64 {
+        1 << (self.level(self.root) - 1)
+    }
+
+    fn level(&self, node: NodeId) -> u8 {
+        self.nodes[node as usize].level
+    }
+
+    fn children(&self, node: NodeId) -> [NodeId; 4] {
+        self.nodes[node as usize].children
+    }
+
+    pub(crate) fn join(&mut self, children: [NodeId; 4]) -> NodeId {
+        if let Some(&node) = self.interned.get(&children) {
+            return node;
+        }
+        let node = Node {
+            children,
+            level: self.level(children[0]) + 1,
+            population: children
+                .iter()
+                .map(|&c| self.nodes[c as usize].population)
+                .sum(),
+        };
+        let id = self.nodes.len() as NodeId;
+        self.nodes.push(node);
+        self.interned.insert(children, id);
+        id
+    }
+
+    pub(crate) fn empty(&mut self, level: u8) -> NodeId {
+        while self.empty.len() <= level as usize {
+            let below = *self.empty.last().unwrap();
+            let node = self.join([below; 4]);
+            self.empty.push(node);
+        }
+        self.empty[level as usize]
+    }
+
+    /// The node one level up with `node` in its center.
+    fn expand(&mut self, node: NodeId) -> NodeId {
+        let e = self.empty(self.level(node) - 1);
+        let [nw, ne, sw, se] = self.children(node);
+        let children = [
+            self.join([e, e, e, nw]),
+            self.join([e, e, ne, e]),
+            self.join([e, sw, e, e]),
+            self.join([se, e, e, e]),
+        ];
+        self.join(children)
+    }
+
+    /// Whether every alive cell of `node` is in its center.
+    fn is_padded(&self, node: NodeId) -> bool {
+        let [nw, ne, sw, se] = self.children(node);
+        let population = |n: NodeId| self.nodes[n as usize].population;
+        population(nw) == population(self.children(nw)[3])
+            && population(ne) == population(self.children(ne)[2])
+            && population(sw) == population(self.children(sw)[1])
+            && population(se) == population(self.children(se)[0])
+    }
+
+    /// The node one level down in the center of `node`.
+    fn center(&mut self, node: NodeId) -> NodeId {
+        let [nw, ne, sw, se] = self.children(node);
+        self.join([
+            self.children(nw)[3],
+            self.children(ne)[2],
+            self.children(sw)[1],
+            self.children(se)[0],
+        ])
+    }
+
+    /// The node straddling the border between `west` and `east`.
+    fn horizontal(&mut self, west: NodeId, east: NodeId) -> NodeId {
+        let [_, w_ne, _, w_se] = self.children(west);
+        let [e_nw, _, e_sw, _] = self.children(east);
+        self.join([w_ne, e_nw, w_se, e_sw])
+    }
+
+    /// The node straddling the border between `north` and `south`.
+    fn vertical(&mut self, north: NodeId, south: NodeId) -> NodeId {
+        let [_, _, n_sw, n_se] = self.children(north);
+        let [s_nw, s_ne, _, _] = self.children(south);
+        self.join([n_sw, n_se, s_nw, s_ne])
+    }
+
+    /// The center of `node` after `2^j` generations, `j` can be at most `level - 2`.
+    fn successor(&mut self, node: NodeId, j: u8) -> NodeId {
+        let Node {
+            children,
+            level,
+            population,
+        } = self.nodes[node as usize];
+        if population == 0 {
+            return self.empty(level - 1);
+        }
+        if let Some(&result) = self.results.get(&(node, j)) {
+            return result;
+        }
+
+        let result = if level == 2 {
+            self.step_4x4(node)
+        } else {
+            let [nw, ne, sw, se] = children;
+            let grid = [
+                nw,
+                self.horizontal(nw, ne),
+                ne,
+                self.vertical(nw, sw),
+                self.center(node),
+                self.vertical(ne, se),
+                sw,
+                self.horizontal(sw, se),
+                se,
+            ];
+
+            // At full speed each half of the jump is done by a recursive step,
+            // otherwise the first half doesn't move forward in time at all.
+            let full_speed = j == level - 2;
+            let mut inner = [DEAD; 9];
+            for (inner, &n) in inner.iter_mut().zip(grid.iter()) {
+                *inner = if full_speed {
+                    self.successor(n, j - 1)
+                } else {
+                    self.center(n)
+                };
+            }
+            let inner_j = if full_speed { j - 1 } else { j };
+
+            let quadrants = [[0, 1, 3, 4], [1, 2, 4, 5], [3, 4, 6, 7], [4, 5, 7, 8]];
+            let mut result = [DEAD; 4];
+            for (result, quadrant) in result.iter_mut().zip(quadrants) {
+                let joined = self.join(quadrant.map(|i| inner[i]));
+                *result = self.successor(joined, inner_j);
+            }
+            self.join(result)
+        };
+
+        self.results.insert((node, j), result);
+        result
+    }
+
+    /// Computes the middle 2x2 of a 4x4 node one generation on directly.
+    fn step_4x4(&mut self, node: NodeId) -> NodeId {
+        let quadrants = self.children(node);
+        let cell = |x: i32, y: i32| -> bool {
+            if !(0..4).contains(&x) || !(0..4).contains(&y) {
+                return false;
+            }
+            let quadrant = quadrants[(x >= 2) as usize + 2 * (y >= 2) as usize];
+            self.children(quadrant)[(x & 1) as usize + 2 * (y & 1) as usize] == ALIVE
+        };
+        let next = |x: i32, y: i32| -> NodeId {
+            let neighbours = (-1..=1)
+                .flat_map(|dy| (-1..=1).map(move |dx| (dx, dy)))
+                .filter(|&(dx, dy)| (dx, dy) != (0, 0) && cell(x + dx, y + dy))
+                .count();
+            let mask = if cell(x, y) { self.survive } else { self.birth };
+            if mask & (1 << neighbours) != 0 {
+                ALIVE
+            } else {
+                DEAD
+            }
+        };
+        let children = [next(1, 1), next(2, 1), next(1, 2), next(2, 2)];
+        self.join(children)
+    }
+
+    fn set_in(&mut self, node: NodeId, ox: i64, oy: i64, x: i64, y: i64, alive: bool) -> NodeId {
+        let level = self.level(node);
+        if level == 0 {
+            return if alive { ALIVE } else { DEAD };
+        }
+        let half = 1i64 << (level - 1);
+        let east = x >= ox + half;
+        let south = y >= oy + half;
+        let quadrant = east as usize + 2 * south as usize;
+        let mut children = self.children(node);
+        children[quadrant] = self.set_in(
+            children[quadrant],
+            ox + if east { half } else { 0 },
+            oy + if south { half } else { 0 },
+            x,
+            y,
+            alive,
+        );
+        self.join(children)
+    }
+
+    /// Throws away every node that isn't part of the current root, along with
+    /// all the cached results.
+    fn collect_garbage(&mut self) {
+        let mut fresh = HashLife::new(self.birth, self.survive);
+        let mut copied = HashMap::default();
+        fresh.root = fresh.copy_from(self, self.root, &mut copied);
+        *self = fresh;
+    }
+
+    fn copy_from(
+        &mut self,
+        other: &HashLife,
+        node: NodeId,
+        copied: &mut HashMap<NodeId, NodeId>,
+    ) -> NodeId {
+        if node == DEAD || node == ALIVE {
+            return node;
+        }
+        if let Some(&id) = copied.get(&node) {
+            return id;
+        }
+        let children = other
+            .children(node)
+            .map(|c| self.copy_from(other, c, copied));
+        let id = self.join(children);
+        copied.insert(node, id);
+        id
+    }
+}
+
+/// The alive cells under a node, found by walking down the tree.
+struct AliveCells<'a> {
+    life: &'a HashLife,
+    /// The nodes left to walk and the positions of their north-west corners.
+    stack: Vec<(NodeId, i64, i64)>,
+}
+
+impl Iterator for AliveCells<'_> {
+    type Item = IVec2;
+
+    fn next(&mut self) -> Option<IVec2> {
+        while let Some((node, ox, oy)) = self.stack.pop() {
+            let Node {
+                children,
+                level,
+                population,
+            } = self.life.nodes[node as usize];
+            if population == 0 {
+                continue;
+            }
+            if level == 0 {
+                if let (Ok(x), Ok(y)) = (i32::try_from(ox), i32::try_from(oy)) {
+                    return Some(IVec2::new(x, y));
+                }
+                continue;
+            }
+            let half = 1i64 << (level - 1);
+            for (quadrant, &child) in children.iter().enumerate() {
+                let dx = if quadrant & 1 != 0 { half } else { 0 };
+                let dy = if quadrant & 2 != 0 { half } else { 0 };
+                self.stack.push((child, ox + dx, oy + dy));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::rules::GameRules;
+    use crate::stochastic::Randomness;
+    use crate::universe::{Engine, Universe};
+
+    fn sorted(cells: impl Iterator<Item = IVec2>) -> Vec<(i32, i32)> {
+        let mut cells = cells.map(|pos| (pos.x, pos.y)).collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn set_and_get() {
+        let mut life = HashLife::new(1 << 3, 1 << 2 | 1 << 3);
+        let far = IVec2::new(-1_000_000, 70_000);
+        for pos in [IVec2::ZERO, IVec2::new(5, -3), far] {
+            life.set(pos, true);
+        }
+        assert!(life.get(far) && life.get(IVec2::new(5, -3)));
+        assert!(!life.get(IVec2::new(-3, 5)));
+        assert_eq!(life.population(), 3);
+        life.set(far, false);
+        assert_eq!(sorted(life.iter_alive()), [(0, 0), (5, -3)]);
+    }
+
+    #[test]
+    fn agrees_with_tiles() {
+        for rule in ["B3/S23", "B36/S23", "B3678/S34678"] {
+            let rules = rule.parse::<GameRules>().unwrap();
+            let (birth, survive) = rules.masks().unwrap();
+            let mut tiles = Universe::new(rules);
+            let mut life = HashLife::new(birth, survive);
+            let mut rng = StdRng::seed_from_u64(1);
+            for _ in 0..600 {
+                let pos = IVec2::new(rng.gen_range(-20..20), rng.gen_range(-20..20));
+                tiles.set(pos, true);
+                life.set(pos, true);
+            }
+            assert_eq!(tiles.engine(), Engine::Tiles);
+
+            // Jumps of every size, so both halves of `successor` are used.
+            let mut randomness = Randomness::new(0);
+            for j in [0, 3, 1, 5, 0, 2, 6] {
+                life.advance(j);
+                for _ in 0..1 << j {
+                    tiles.step(&mut randomness);
+                }
+                assert_eq!(
+                    sorted(life.iter_alive()),
+                    sorted(tiles.iter_alive()),
+                    "{} after a jump of 2^{}",
+                    rule,
+                    j
+                );
+                assert_eq!(life.population(), tiles.population());
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! [Hensel notation](https://conwaylife.com/wiki/Isotropic_non-totalistic_rule) for
+//! isotropic non-totalistic rules, where a letter after each neighbour count says
+//! which arrangements of that many neighbours it means.
+
+use crate::rules::{NeighbourhoodSet, RuleParseError};
+
+/// Where each neighbour is, in the order of their bits in a neighbourhood.
+const POSITIONS: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (-1, 0),
+    (1, 0),
+    (-1, 1),
+    (0, 1),
+    (1, 1),
+];
+
+/// The letters for each count of up to four neighbours, in Golly's order, along
+/// with one neighbourhood they stand for. Counts above four use the complement
+/// of the neighbourhoods for `8 - count`.
+const LETTERS: [&[(char, u8)]; 5] = [
+    &[],
+    &[('c', 0b10000000), ('e', 0b01000000)],
+    &[
+        ('c', 0b10100000),
+        ('e', 0b01010000),
+        ('a', 0b11000000),
+        ('i', 0b00011000),
+        ('k', 0b10001000),
+        ('n', 0b00100100),
+    ],
+    &[
+        ('c', 0b10100100),
+        ('e', 0b01011000),
+        ('a', 0b11010000),
+        ('i', 0b11100000),
+        ('k', 0b01001100),
+        ('n', 0b10110000),
+        ('j', 0b01110000),
+        ('q', 0b01100100),
+        ('r', 0b10011000),
+        ('y', 0b10001100),
+    ],
+    &[
+        ('c', 0b10100101),
+        ('e', 0b01011010),
+        ('a', 0b11110000),
+        ('i', 0b10111000),
+        ('k', 0b11001100),
+        ('n', 0b11100100),
+        ('j', 0b01011100),
+        ('q', 0b01101100),
+        ('r', 0b11011000),
+        ('y', 0b10101100),
+        ('t', 0b10011100),
+        ('w', 0b01110100),
+        ('z', 0b00111100),
+    ],
+];
+
+/// The letters for `count` neighbours with the neighbourhood each stands for.
+fn letters(count: u32) -> impl Iterator<Item = (char, u8)> {
+    let (letters, invert) = match count {
+        0..=4 => (LETTERS[count as usize], 0),
+        _ => (LETTERS[8 - count as usize], u8::MAX),
+    };
+    letters.iter().map(move |&(letter, n)| (letter, n ^ invert))
+}
+
+/// Every rotation and reflection of `neighbours`.
+fn symmetries(neighbours: u8) -> impl Iterator<Item = u8> {
+    (0..8).map(move |symmetry| {
+        let transform = |(mut x, mut y): (i32, i32)| {
+            for _ in 0..symmetry % 4 {
+                (x, y) = (-y, x);
+            }
+            if symmetry >= 4 {
+                x = -x;
+            }
+            (x, y)
+        };
+        (0..8)
+            .filter(|i| neighbours & 1 << i != 0)
+            .map(|i| {
+                let moved = transform(POSITIONS[i]);
+                1 << POSITIONS.iter().position(|&p| p == moved).unwrap()
+            })
+            .fold(0, |acc, bit| acc | bit)
+    })
+}
+
+/// Parses the neighbourhoods of a birth or survival section without its letter,
+/// like `3-cnqy` or `23-a4itz`.
+pub(crate) fn parse(section: &str) -> Result<NeighbourhoodSet, RuleParseError> {
+    let mut set = NeighbourhoodSet::default();
+    let mut chars = section.chars().peekable();
+    while let Some(c) = chars.next() {
+        let count = match c.to_digit(10) {
+            Some(n) if n <= 8 => n,
+            _ => return Err(RuleParseError::InvalidNeighbourCount(c)),
+        };
+        let exclude = chars.next_if_eq(&'-').is_some();
+        let mut named = Vec::new();
+        while let Some(letter) = chars.next_if(char::is_ascii_lowercase) {
+            if !letters(count).any(|(l, _)| l == letter) {
+                return Err(RuleParseError::InvalidLetter(count, letter));
+            }
+            named.push(letter);
+        }
+
+        // No letters means every arrangement of that many neighbours.
+        let included = |letter: char| named.is_empty() || named.contains(&letter) != exclude;
+        if count == 0 || count == 8 {
+            set.insert(if count == 0 { 0 } else { u8::MAX });
+        }
+        for (_, neighbours) in letters(count).filter(|&(l, _)| included(l)) {
+            for n in symmetries(neighbours) {
+                set.insert(n);
+            }
+        }
+    }
+    Ok(set)
+}
+
+/// Writes `set` in Hensel notation, using `-` when it's shorter.
+pub(crate) fn format(set: &NeighbourhoodSet) -> String {
+    let mut out = String::new();
+    for count in 0..=8 {
+        if count == 0 || count == 8 {
+            if set.contains(if count == 0 { 0 } else { u8::MAX }) {
+                out.push(char::from(b'0' + count as u8));
+            }
+            continue;
+        }
+        let (present, missing): (Vec<_>, Vec<_>) =
+            letters(count).partition(|&(_, n)| set.contains(n));
+        if present.is_empty() {
+            continue;
+        }
+        out.push(char::from(b'0' + count as u8));
+        if missing.is_empty() {
+            continue;
+        }
+        if present.len() <= missing.len() {
+            out.extend(present.iter().map(|&(l, _)| l));
+        } else {
+            out.push('-');
+            out.extend(missing.iter().map(|&(l, _)| l));
+        }
+    }
+    out
+}
+
+/// The counts `set` is made of, if it has either every arrangement of a count
+/// or none of them, like a Life-like rule.
+pub(crate) fn totalistic(set: &NeighbourhoodSet) -> Option<u16> {
+    let mut mask = 0;
+    for count in 0..=8 {
+        let mut arrangements = (0..=u8::MAX).filter(|n| n.count_ones() == count);
+        if arrangements.clone().all(|n| set.contains(n)) {
+            mask |= 1 << count;
+        } else if arrangements.any(|n| set.contains(n)) {
+            return None;
+        }
+    }
+    Some(mask)
+}
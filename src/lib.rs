@@ -0,0 +1,18 @@
+//! The simulation core, independent of the Bevy app in `main.rs`.
+
+pub mod automaton;
+pub mod bounded;
+pub mod continuous;
+mod fft;
+pub mod hashlife;
+mod hensel;
+pub mod ltl;
+pub mod macrocell;
+pub mod margolus;
+pub mod neighbourhood;
+pub mod rules;
+pub mod ruletable;
+pub mod stochastic;
+mod tile;
+pub mod universe;
+pub mod wolfram;
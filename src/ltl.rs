@@ -0,0 +1,342 @@
+//! [Larger than Life](https://conwaylife.com/wiki/Larger_than_Life) rules, where
+//! cells count their neighbours over a large range, written like Golly's
+//! `R5,C0,M1,S34..58,B34..45,NM`.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use bevy::math::IVec2;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::neighbourhood::Neighbourhood;
+use crate::rules::RuleParseError;
+
+/// The largest range Golly allows.
+const MAX_RANGE: u32 = 500;
+
+/// The width and height of the chunks cells are grouped into to count them.
+const CHUNK_SIZE: i32 = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargerThanLife {
+    /// How far away a cell's neighbours can be.
+    pub range: u32,
+    /// How many states cells have, above two the extra ones are dying states
+    /// like in Generations rules.
+    pub states: u8,
+    /// Whether cells count themselves as a neighbour.
+    pub middle: bool,
+    pub survive: RangeInclusive<u32>,
+    pub birth: RangeInclusive<u32>,
+    /// Either the Moore square or the von Neumann diamond of `range`.
+    pub neighbourhood: Neighbourhood,
+}
+
+impl LargerThanLife {
+    /// The state a cell in `state` moves to with `alive_neighbours` neighbours in state `1`.
+    pub fn next_state(&self, state: u8, alive_neighbours: u32) -> u8 {
+        match state {
+            0 => self.birth.contains(&alive_neighbours) as u8,
+            1 if self.survive.contains(&alive_neighbours) => 1,
+            _ if state.saturating_add(1) < self.states => state + 1,
+            _ => 0,
+        }
+    }
+
+    /// Whether `rule` looks like a Larger than Life rule rather than a `B/S` one.
+    pub(crate) fn matches(rule: &str) -> bool {
+        rule.starts_with(['R', 'r']) && rule.contains(',')
+    }
+
+    /// How far either side of the centre each row of the neighbourhood goes, from
+    /// the row `range` below to the one `range` above.
+    fn row_widths(&self) -> Vec<i32> {
+        let range = self.range as i32;
+        (-range..=range)
+            .map(|dy| match self.neighbourhood {
+                Neighbourhood::VonNeumann => range - dy.abs(),
+                _ => range,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for LargerThanLife {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let states = if self.states == 2 { 0 } else { self.states };
+        write!(
+            f,
+            "R{},C{},M{},S{}..{},B{}..{},N{}",
+            self.range,
+            states,
+            self.middle as u8,
+            self.survive.start(),
+            self.survive.end(),
+            self.birth.start(),
+            self.birth.end(),
+            if self.neighbourhood == Neighbourhood::VonNeumann {
+                'N'
+            } else {
+                'M'
+            }
+        )
+    }
+}
+
+impl std::str::FromStr for LargerThanLife {
+    type Err = RuleParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |part: &str| RuleParseError::InvalidParameter(part.to_string());
+        let (mut range, mut states, mut middle) = (None, 0, false);
+        let (mut survive, mut birth) = (None, None);
+        let mut neighbourhood = Neighbourhood::Moore;
+
+        for part in s.split(',').map(str::trim) {
+            let mut chars = part.chars();
+            let key = chars.next().map(|c| c.to_ascii_uppercase());
+            let value = chars.as_str();
+            let counts = || {
+                let (low, high) = value.split_once("..").ok_or_else(|| invalid(part))?;
+                match (low.parse::<u32>(), high.parse::<u32>()) {
+                    (Ok(low), Ok(high)) => Ok(low..=high),
+                    _ => Err(invalid(part)),
+                }
+            };
+            match key {
+                Some('R') => match value.parse() {
+                    Ok(r) if (1..=MAX_RANGE).contains(&r) => range = Some(r),
+                    _ => return Err(invalid(part)),
+                },
+                Some('C') => match value.parse::<u8>() {
+                    Ok(c) if c != 1 => states = c,
+                    _ => return Err(invalid(part)),
+                },
+                Some('M') => match value {
+                    "0" => middle = false,
+                    "1" => middle = true,
+                    _ => return Err(invalid(part)),
+                },
+                Some('S') => survive = Some(counts()?),
+                Some('B') => birth = Some(counts()?),
+                Some('N') => match value {
+                    "M" | "m" => neighbourhood = Neighbourhood::Moore,
+                    "N" | "n" => neighbourhood = Neighbourhood::VonNeumann,
+                    _ => return Err(invalid(part)),
+                },
+                _ => return Err(invalid(part)),
+            }
+        }
+
+        match (range, survive, birth) {
+            // Only the cells in range of alive ones are stepped.
+            (_, _, Some(birth)) if birth.contains(&0) => Err(RuleParseError::Unsupported(
+                "Larger than Life rules with `B0`",
+            )),
+            (Some(range), Some(survive), Some(birth)) => Ok(LargerThanLife {
+                range,
+                // Both `C0` and `C2` mean two states.
+                states: states.max(2),
+                middle,
+                survive,
+                birth,
+                neighbourhood,
+            }),
+            _ => Err(RuleParseError::Malformed),
+        }
+    }
+}
+
+/// Computes the next state of every cell that isn't dead and of every cell in
+/// range of one.
+///
+/// Cells are grouped into chunks, and each group of chunks that touch gets one
+/// summed area table of the alive cells in range of it, so counting a row of
+/// neighbours takes the same time however long it is.
+pub(crate) fn step(cells: &HashMap<IVec2, u8>, rule: &LargerThanLife) -> HashMap<IVec2, u8> {
+    let range = rule.range as i32;
+    let chunk_of =
+        |pos: IVec2| IVec2::new(pos.x.div_euclid(CHUNK_SIZE), pos.y.div_euclid(CHUNK_SIZE));
+
+    // A bit per alive cell, each chunk's rows from the bottom.
+    let mut alive: HashMap<IVec2, [u64; CHUNK_SIZE as usize]> = HashMap::default();
+    for (&pos, _) in cells.iter().filter(|(_, &state)| state == 1) {
+        let local = pos - chunk_of(pos) * CHUNK_SIZE;
+        let rows = alive
+            .entry(chunk_of(pos))
+            .or_insert([0; CHUNK_SIZE as usize]);
+        rows[local.y as usize] |= 1 << local.x;
+    }
+    let is_alive = |pos: IVec2| {
+        let local = pos - chunk_of(pos) * CHUNK_SIZE;
+        alive
+            .get(&chunk_of(pos))
+            .is_some_and(|rows| rows[local.y as usize] >> local.x & 1 != 0)
+    };
+
+    // How many chunks away alive cells can affect.
+    let reach = (range + CHUNK_SIZE - 1) / CHUNK_SIZE;
+    let around = |chunk: IVec2, distance: i32| {
+        (-distance..=distance)
+            .flat_map(move |dy| (-distance..=distance).map(move |dx| chunk + IVec2::new(dx, dy)))
+    };
+    let mut active = cells
+        .keys()
+        .map(|&pos| chunk_of(pos))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .flat_map(|chunk| around(chunk, reach))
+        .collect::<HashSet<_>>();
+
+    let row_widths = rule.row_widths();
+    let mut next = HashMap::default();
+    while let Some(&first) = active.iter().next() {
+        // The chunks touching `first`, directly or through each other.
+        active.remove(&first);
+        let mut group = vec![first];
+        let mut i = 0;
+        while i < group.len() {
+            for chunk in around(group[i], 1) {
+                if active.remove(&chunk) {
+                    group.push(chunk);
+                }
+            }
+            i += 1;
+        }
+        let min = group.iter().fold(first, |min, &chunk| min.min(chunk));
+        let max = group.iter().fold(first, |max, &chunk| max.max(chunk));
+
+        // `sums[y * stride + x]` is how many cells are alive below and left of
+        // `(x, y)`, from the bottom left corner of the group's range.
+        let origin = min * CHUNK_SIZE - IVec2::splat(range);
+        let size = (max - min + IVec2::ONE) * CHUNK_SIZE + IVec2::splat(2 * range);
+        let stride = (size.x + 1) as usize;
+        let mut sums = vec![0u32; stride * (size.y + 1) as usize];
+        for y in 0..size.y {
+            let mut row = 0;
+            for x in 0..size.x {
+                row += is_alive(origin + IVec2::new(x, y)) as u32;
+                let i = (y as usize + 1) * stride + x as usize + 1;
+                sums[i] = sums[i - stride] + row;
+            }
+        }
+        // The alive cells from `(x0, y0)` to `(x1, y1)` inclusive.
+        let sum = |x0: i32, y0: i32, x1: i32, y1: i32| {
+            let at = |x: i32, y: i32| sums[y as usize * stride + x as usize];
+            at(x1 + 1, y1 + 1) + at(x0, y0) - at(x0, y1 + 1) - at(x1 + 1, y0)
+        };
+
+        for chunk in group {
+            let corner = chunk * CHUNK_SIZE - origin;
+            for y in corner.y..corner.y + CHUNK_SIZE {
+                for x in corner.x..corner.x + CHUNK_SIZE {
+                    let mut count = match rule.neighbourhood {
+                        Neighbourhood::VonNeumann => row_widths
+                            .iter()
+                            .zip(-range..=range)
+                            .map(|(&w, dy)| sum(x - w, y + dy, x + w, y + dy))
+                            .sum(),
+                        _ => sum(x - range, y - range, x + range, y + range),
+                    };
+                    let pos = origin + IVec2::new(x, y);
+                    let state = cells.get(&pos).copied().unwrap_or(0);
+                    if state == 1 && !rule.middle {
+                        count -= 1;
+                    }
+                    let state = rule.next_state(state, count);
+                    if state != 0 {
+                        next.insert(pos, state);
+                    }
+                }
+            }
+        }
+    }
+    next
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /// The next generation counted a cell at a time.
+    fn naive_step(cells: &HashMap<IVec2, u8>, rule: &LargerThanLife) -> HashMap<IVec2, u8> {
+        let range = rule.range as i32;
+        let widths = rule.row_widths();
+        let neighbours = |pos: IVec2| {
+            (-range..=range)
+                .flat_map(|dy| {
+                    let width = widths[(dy + range) as usize];
+                    (-width..=width).map(move |dx| pos + IVec2::new(dx, dy))
+                })
+                .filter(|&at| at != pos || rule.middle)
+                .filter(|at| cells.get(at) == Some(&1))
+                .count() as u32
+        };
+        let candidates = cells
+            .keys()
+            .flat_map(|&pos| {
+                (-range..=range)
+                    .flat_map(move |dy| (-range..=range).map(move |dx| pos + IVec2::new(dx, dy)))
+            })
+            .collect::<HashSet<_>>();
+        candidates
+            .into_iter()
+            .map(|pos| {
+                let state = cells.get(&pos).copied().unwrap_or(0);
+                (pos, rule.next_state(state, neighbours(pos)))
+            })
+            .filter(|&(_, state)| state != 0)
+            .collect()
+    }
+
+    #[test]
+    fn agrees_with_counting() {
+        for rule in [
+            "R3,C0,M1,S5..12,B6..9,NM",
+            "R2,C4,M0,S3..6,B4..5,NN",
+            "R5,C0,M1,S34..58,B34..45,NM",
+        ] {
+            let rule = rule.parse::<LargerThanLife>().unwrap();
+            let mut rng = StdRng::seed_from_u64(3);
+            // Across the edges of chunks, so groups of them are counted together.
+            let mut cells = (0..1500)
+                .map(|_| {
+                    let pos = IVec2::new(rng.gen_range(-80..80), rng.gen_range(-20..20));
+                    (pos, 1)
+                })
+                .collect::<HashMap<_, _>>();
+            for generation in 1..=4 {
+                let next = step(&cells, &rule);
+                assert_eq!(
+                    next,
+                    naive_step(&cells, &rule),
+                    "{} at {}",
+                    rule,
+                    generation
+                );
+                cells = next;
+            }
+        }
+    }
+
+    #[test]
+    fn parses_like_golly() {
+        let rule = "R5,C0,M1,S34..58,B34..45,NM"
+            .parse::<LargerThanLife>()
+            .unwrap();
+        assert_eq!((rule.range, rule.states, rule.middle), (5, 2, true));
+        assert_eq!((rule.survive, rule.birth), (34..=58, 34..=45));
+        assert_eq!(
+            "r10,c3,m0,s1..2,b3..3,nn".parse::<LargerThanLife>(),
+            "R10,C3,M0,S1..2,B3..3,NN".parse()
+        );
+        assert!("R5,C0,M1,S34..58,NM".parse::<LargerThanLife>().is_err());
+        assert!("R501,C0,M1,S1..2,B3..3,NM"
+            .parse::<LargerThanLife>()
+            .is_err());
+        assert!("R5,C0,M1,S1..2,B0..3,NM".parse::<LargerThanLife>().is_err());
+    }
+}
@@ -0,0 +1,350 @@
+//! Reading and writing Golly's [Macrocell](https://conwaylife.com/wiki/Macrocell) format,
+//! which stores a pattern as its deduplicated quadtree.
+
+use std::fmt;
+
+use bevy::math::IVec2;
+use bevy::utils::HashMap;
+
+use crate::hashlife::{HashLife, NodeId};
+use crate::rules::{GameRules, RuleParseError};
+use crate::ruletable::RuleTable;
+use crate::universe::Universe;
+
+/// The highest level of node that can be read, any higher and the coordinates
+/// of its cells don't fit in an `i64`.
+const MAX_LEVEL: usize = 63;
+
+/// The cells of a pattern read from a Macrocell file, where Golly puts them: the
+/// root centered on the origin, but with y increasing upwards.
+#[derive(Debug, Clone)]
+pub enum MacrocellCells {
+    /// A two state pattern, as the tree the file describes.
+    Tree(HashLife),
+    /// The cells of a multi-state pattern that aren't dead and their states.
+    States(Vec<(IVec2, u8)>),
+}
+
+/// A pattern read from a Macrocell file.
+#[derive(Debug, Clone)]
+pub struct Macrocell {
+    pub cells: MacrocellCells,
+    pub rule: Option<GameRules>,
+    /// The name of a rule that isn't built in, to be read from a `.rule` file.
+    pub rule_file: Option<String>,
+    pub generation: u64,
+}
+
+#[derive(Debug)]
+pub enum MacrocellError {
+    MissingHeader,
+    BadRule(RuleParseError),
+    /// A node line that couldn't be understood, with its line number.
+    BadNode(usize),
+    /// A node refers to one that hasn't been defined yet.
+    UnknownNode(usize),
+    TooLarge,
+}
+
+impl fmt::Display for MacrocellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacrocellError::MissingHeader => write!(f, "missing `[M2]` header"),
+            MacrocellError::BadRule(e) => write!(f, "bad rule: {}", e),
+            MacrocellError::BadNode(line) => write!(f, "line {}: malformed node", line),
+            MacrocellError::UnknownNode(line) => {
+                write!(f, "line {}: refers to a node that isn't defined yet", line)
+            }
+            MacrocellError::TooLarge => write!(f, "pattern is too large to place"),
+        }
+    }
+}
+
+impl std::error::Error for MacrocellError {}
+
+enum Node {
+    /// An 8x8 square, one byte per row with bit `x` holding column `x`.
+    Leaf([u8; 8]),
+    /// A 2x2 square of a multi-state pattern, the states of its north-west,
+    /// north-east, south-west and south-east cells.
+    States([u8; 4]),
+    Branch {
+        level: usize,
+        children: [usize; 4],
+    },
+}
+
+impl Node {
+    fn level(&self) -> usize {
+        match self {
+            Node::Leaf(_) => 3,
+            Node::States(_) => 1,
+            Node::Branch { level, .. } => *level,
+        }
+    }
+}
+
+/// Parses a Macrocell file, which must already be decompressed.
+pub fn parse(input: &str) -> Result<Macrocell, MacrocellError> {
+    let mut lines = input
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (i + 1, l.trim_end()));
+    match lines.next() {
+        Some((_, header)) if header.starts_with("[M2]") => {}
+        _ => return Err(MacrocellError::MissingHeader),
+    }
+
+    let (mut rule, mut rule_file) = (None, None);
+    let mut generation = 0;
+    // Node 0 is always the empty node, the file's nodes are numbered from 1.
+    let mut nodes = vec![Node::Leaf([0; 8])];
+    for (number, line) in lines {
+        if let Some(comment) = line.strip_prefix('#') {
+            if let Some(r) = comment.strip_prefix('R') {
+                // Like Golly, a rule that isn't built in is read from its `.rule` file.
+                match r.trim().parse() {
+                    Ok(r) => rule = Some(r),
+                    Err(_) if RuleTable::is_name(r.trim()) => {
+                        rule_file = Some(r.trim().to_string())
+                    }
+                    Err(e) => return Err(MacrocellError::BadRule(e)),
+                }
+            } else if let Some(g) = comment.strip_prefix('G') {
+                generation = g
+                    .trim()
+                    .parse()
+                    .map_err(|_| MacrocellError::BadNode(number))?;
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.starts_with(['.', '*', '$']) {
+            let mut rows = [0u8; 8];
+            for (y, row) in line.split('$').take(8).enumerate() {
+                for (x, c) in row.chars().enumerate() {
+                    match c {
+                        '*' if x < 8 => rows[y] |= 1 << x,
+                        '.' if x < 8 => {}
+                        _ => return Err(MacrocellError::BadNode(number)),
+                    }
+                }
+            }
+            nodes.push(Node::Leaf(rows));
+        } else {
+            let numbers = line
+                .split_whitespace()
+                .map(|n| n.parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| MacrocellError::BadNode(number))?;
+            let node = match numbers[..] {
+                // Multi-state patterns have 2x2 leaves of states instead of 8x8 ones.
+                [1, nw, ne, sw, se] => {
+                    let states = [nw, ne, sw, se].map(u8::try_from);
+                    match states {
+                        [Ok(nw), Ok(ne), Ok(sw), Ok(se)] => Node::States([nw, ne, sw, se]),
+                        _ => return Err(MacrocellError::BadNode(number)),
+                    }
+                }
+                [level, nw, ne, sw, se] if (2..=MAX_LEVEL).contains(&level) => {
+                    let children = [nw, ne, sw, se];
+                    if children.iter().any(|&c| c >= nodes.len()) {
+                        return Err(MacrocellError::UnknownNode(number));
+                    }
+                    if children
+                        .iter()
+                        .any(|&c| c != 0 && nodes[c].level() != level - 1)
+                    {
+                        return Err(MacrocellError::BadNode(number));
+                    }
+                    Node::Branch { level, children }
+                }
+                _ => return Err(MacrocellError::BadNode(number)),
+            };
+            nodes.push(node);
+        }
+    }
+
+    let multi_state = nodes
+        .iter()
+        .any(|node| matches!(node, Node::States(states) if states.iter().any(|&s| s > 1)));
+    let cells = if multi_state {
+        MacrocellCells::States(collect_states(&nodes)?)
+    } else {
+        MacrocellCells::Tree(build_tree(&nodes)?)
+    };
+    Ok(Macrocell {
+        cells,
+        rule,
+        rule_file,
+        generation,
+    })
+}
+
+/// Builds the HashLife tree of a two state pattern a node at a time, so however
+/// many cells it has they're never gone through one by one.
+fn build_tree(nodes: &[Node]) -> Result<HashLife, MacrocellError> {
+    let mut life = HashLife::new(0, 0);
+    if nodes.len() == 1 {
+        return Ok(life);
+    }
+
+    // Macrocell has y increasing downwards, so each node is flipped over. The
+    // root covers the same cells either way up, as it's centered on the origin.
+    let mut ids: Vec<NodeId> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let id = match node {
+            Node::Leaf(rows) => life.square(3, &|x, y| rows[7 - y as usize] >> x & 1 != 0),
+            Node::States(states) => life.square(1, &|x, y| states[(x + 2 * (1 - y)) as usize] != 0),
+            Node::Branch { level, children } => {
+                let [nw, ne, sw, se] = children.map(|c| match c {
+                    0 => life.empty(*level as u8 - 1),
+                    _ => ids[c],
+                });
+                life.join([sw, se, nw, ne])
+            }
+        };
+        ids.push(id);
+    }
+    life.set_root(ids[nodes.len() - 1]);
+    // Past level 32 the pattern goes further from the origin than an `i32` can.
+    if life.root_level() > 32 {
+        return Err(MacrocellError::TooLarge);
+    }
+    Ok(life)
+}
+
+/// The states of every cell of a multi-state pattern that isn't dead.
+fn collect_states(nodes: &[Node]) -> Result<Vec<(IVec2, u8)>, MacrocellError> {
+    let root = nodes.len() - 1;
+    let half = 1i64 << (nodes[root].level() - 1);
+    let mut cells = Vec::new();
+    collect_cells(nodes, root, -half, -half, &mut cells);
+    cells
+        .into_iter()
+        .map(
+            |((x, y), state)| match (i32::try_from(x), i32::try_from(-y - 1)) {
+                (Ok(x), Ok(y)) => Ok((IVec2::new(x, y), state)),
+                _ => Err(MacrocellError::TooLarge),
+            },
+        )
+        .collect()
+}
+
+fn collect_cells(nodes: &[Node], node: usize, ox: i64, oy: i64, cells: &mut Vec<((i64, i64), u8)>) {
+    if node == 0 {
+        return;
+    }
+    match &nodes[node] {
+        Node::Leaf(rows) => {
+            for (y, &row) in rows.iter().enumerate() {
+                for x in (0..8).filter(|x| row & (1 << x) != 0) {
+                    cells.push(((ox + x as i64, oy + y as i64), 1));
+                }
+            }
+        }
+        Node::States(states) => {
+            for (quadrant, &state) in states.iter().enumerate().filter(|(_, &s)| s != 0) {
+                let (dx, dy) = (quadrant as i64 & 1, quadrant as i64 >> 1);
+                cells.push(((ox + dx, oy + dy), state));
+            }
+        }
+        Node::Branch { level, children } => {
+            let half = 1i64 << (level - 1);
+            for (quadrant, &child) in children.iter().enumerate() {
+                let dx = if quadrant & 1 != 0 { half } else { 0 };
+                let dy = if quadrant & 2 != 0 { half } else { 0 };
+                collect_cells(nodes, child, ox + dx, oy + dy, cells);
+            }
+        }
+    }
+}
+
+/// Writes every cell of `universe` that isn't dead as a Macrocell file, along
+/// with its rule and generation. Patterns with more states than alive and dead
+/// are written with Golly's multi-state `1 a b c d` leaves.
+pub fn write(universe: &Universe) -> String {
+    let mut out = format!(
+        "[M2] (automata {})\n#R {}\n#G {}\n",
+        env!("CARGO_PKG_VERSION"),
+        universe.rules(),
+        universe.generation()
+    );
+
+    if universe.rules().states() > 2 {
+        // Macrocell has y increasing downwards, so flip the pattern over.
+        let cells = universe
+            .iter_states()
+            .map(|(pos, state)| ((pos.x as i64, -(pos.y as i64) - 1), state));
+        write_states(cells.collect(), &mut out);
+    } else if let Some(life) = universe.tree() {
+        life.write_macrocell_nodes(&mut out);
+    } else {
+        let mut life = HashLife::new(0, 0);
+        for pos in universe.iter_alive() {
+            life.set(pos, true);
+        }
+        life.write_macrocell_nodes(&mut out);
+    }
+    out
+}
+
+/// Writes the node lines of a multi-state pattern whose root is centered on the
+/// origin, each node after the ones it refers to and the root last.
+fn write_states(cells: Vec<((i64, i64), u8)>, out: &mut String) {
+    // The root has to reach from `-half` to `half - 1` to cover every cell.
+    let extent = cells
+        .iter()
+        .map(|&((x, y), _)| (-x).max(x + 1).max(-y).max(y + 1))
+        .max()
+        .unwrap_or(0);
+    let mut level = 1;
+    while 1i64 << (level - 1) < extent {
+        level += 1;
+    }
+    let half = 1i64 << (level - 1);
+    write_state_node(level, -half, -half, cells, &mut HashMap::default(), out);
+}
+
+/// Writes the node of the `2^level` square from `(ox, oy)` holding `cells` if it
+/// hasn't been already, and returns its number.
+fn write_state_node(
+    level: u32,
+    ox: i64,
+    oy: i64,
+    cells: Vec<((i64, i64), u8)>,
+    numbers: &mut HashMap<(u32, [usize; 4]), usize>,
+    out: &mut String,
+) -> usize {
+    if cells.is_empty() {
+        return 0;
+    }
+    let mut children = [0; 4];
+    if level == 1 {
+        for ((x, y), state) in cells {
+            children[(x - ox + 2 * (y - oy)) as usize] = state as usize;
+        }
+    } else {
+        let half = 1i64 << (level - 1);
+        let mut quadrants: [Vec<_>; 4] = Default::default();
+        for ((x, y), state) in cells {
+            let quadrant = (x >= ox + half) as usize + 2 * (y >= oy + half) as usize;
+            quadrants[quadrant].push(((x, y), state));
+        }
+        for (quadrant, cells) in quadrants.into_iter().enumerate() {
+            let dx = if quadrant & 1 != 0 { half } else { 0 };
+            let dy = if quadrant & 2 != 0 { half } else { 0 };
+            children[quadrant] = write_state_node(level - 1, ox + dx, oy + dy, cells, numbers, out);
+        }
+    }
+
+    let next = numbers.len() + 1;
+    *numbers.entry((level, children)).or_insert_with(|| {
+        let [nw, ne, sw, se] = children;
+        out.push_str(&format!("{} {} {} {} {}\n", level, nw, ne, sw, se));
+        next
+    })
+}
@@ -1,86 +1,153 @@
+use automata::stochastic::Randomness;
+use automata::universe::Universe;
 use bevy::prelude::*;
-use board::{Alive, Board, BoardPosition, GamePlaying};
+use board::GamePlaying;
 use board_asset::BoardAsset;
 use hoverable::Hovering;
+use message::ShowMessage;
+use render::TilePosition;
+use save::PatternInfo;
+use view::View;
 
 mod basic_setup;
 mod board;
 mod board_asset;
 mod hoverable;
+mod message;
 mod render;
+mod save;
+mod selection;
 mod view;
 
+/// The pattern given on the command line, if there was one, and whether it's
+/// been placed yet.
 #[derive(Debug)]
-struct InitialBoard(Handle<BoardAsset>, bool);
+struct InitialBoard(Option<Handle<BoardAsset>>, bool);
 
 #[derive(Debug)]
 struct PauseTimer(Timer, bool);
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let file = std::env::args().nth(1).unwrap();
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut messages: EventWriter<ShowMessage>,
+) {
+    // Stochastic rules are replayed exactly by giving the seed of an earlier run.
+    let randomness = match std::env::args().nth(2).map(|seed| seed.parse()) {
+        Some(Ok(seed)) => Randomness::new(seed),
+        Some(Err(_)) => {
+            let message = "The seed must be a whole number, using a random one";
+            eprintln!("{}", message);
+            messages.send(ShowMessage(message.to_string()));
+            Randomness::default()
+        }
+        None => Randomness::default(),
+    };
+    println!("Seed: {}", randomness.seed());
+    commands.insert_resource(randomness);
+
+    let file = match std::env::args().nth(1) {
+        Some(file) => file,
+        None => {
+            let message = "No pattern given, usage: automata <pattern file> [seed]";
+            eprintln!("{}", message);
+            messages.send(ShowMessage(message.to_string()));
+            commands.insert_resource(InitialBoard(None, true));
+            commands.insert_resource(PatternInfo::default());
+            return;
+        }
+    };
     println!("Loading: {}", file);
     let initial_board_handle = asset_server.load(&file);
-    commands.insert_resource(InitialBoard(initial_board_handle, false));
+    commands.insert_resource(InitialBoard(Some(initial_board_handle), false));
+
+    let name = std::path::Path::new(&file)
+        .file_stem()
+        .map_or(file.clone(), |s| s.to_string_lossy().into_owned());
+    commands.insert_resource(PatternInfo {
+        name,
+        comments: Vec::new(),
+    });
 }
 
 fn intital_board_setup(
     mut initial_board: ResMut<InitialBoard>,
-    mut board: ResMut<Board>,
+    mut universe: ResMut<Universe>,
+    mut info: ResMut<PatternInfo>,
+    mut randomness: ResMut<Randomness>,
     assets: Res<Assets<BoardAsset>>,
-    mut commands: Commands,
 ) {
     if initial_board.1 {
         return;
     }
-    let cell_count = board.size();
 
-    let initial_board_asset = assets.get(&initial_board.0).unwrap();
+    // Nothing is placed until the pattern has loaded, or at all if it failed to.
+    let initial_board_asset = match initial_board.0.as_ref().and_then(|h| assets.get(h)) {
+        Some(asset) => asset,
+        None => return,
+    };
 
-    let board_offset = (
-        (cell_count.x as i32 - initial_board_asset.size.0 as i32) / 2,
-        (cell_count.y as i32 - initial_board_asset.size.1 as i32) / 2,
-    );
+    if let Some(rule) = &initial_board_asset.rule {
+        println!("Using rule: {}", rule);
+        universe.set_rules(rule.clone());
+    }
+    if let Some(name) = &initial_board_asset.name {
+        info.name = name.clone();
+    }
+    info.comments = initial_board_asset.comments.clone();
 
-    for (y, line) in initial_board_asset.data.iter().enumerate() {
-        for (x, &is_set) in line.iter().enumerate() {
-            let pos = IVec2::new(
-                x as i32 + board_offset.0,
-                (initial_board_asset.size.1 as i32 - y as i32) + board_offset.1,
+    universe.set_generation(initial_board_asset.generation);
+    if let Some(tree) = &initial_board_asset.tree {
+        universe.add_tree(tree.clone());
+    }
+    for &(cell, state) in &initial_board_asset.cells {
+        let pos = if initial_board_asset.absolute {
+            IVec2::new(cell.x, -cell.y)
+        } else {
+            // Center the pattern on the origin.
+            let board_offset = (
+                -(initial_board_asset.size.0 as i32) / 2,
+                -(initial_board_asset.size.1 as i32) / 2,
             );
-            let maybe_tile = board.get(pos);
-            if let Some(tile) = maybe_tile {
-                if is_set {
-                    commands.entity(*tile).insert(Alive);
-                }
-            } else {
-                let entity = {
-                    let mut e = commands.spawn();
-                    e.insert(BoardPosition(pos));
-                    if is_set {
-                        e.insert(Alive);
-                    }
-                    e
-                }
-                .id();
-                board.insert(pos, entity);
-            }
-        }
+            IVec2::new(
+                cell.x + board_offset.0,
+                (initial_board_asset.size.1 as i32 - cell.y) + board_offset.1,
+            )
+        };
+        universe.set_state(pos, state);
     }
+    // Start the run from the beginning of the seed, whenever the pattern loaded.
+    randomness.reseed();
     initial_board.1 = true;
 }
 
 fn board_click(
     mouse_input: Res<Input<MouseButton>>,
-    mut alive: Query<Entity, (With<Hovering>, With<Alive>)>,
-    mut dead: Query<Entity, (With<Hovering>, Without<Alive>)>,
-    mut commands: Commands,
+    hovering: Query<&TilePosition, With<Hovering>>,
+    view: Query<&View>,
+    mut universe: ResMut<Universe>,
 ) {
     if mouse_input.just_pressed(MouseButton::Left) {
-        for entity in alive.iter_mut() {
-            commands.entity(entity).remove::<Alive>();
-        }
-        for entity in dead.iter_mut() {
-            commands.entity(entity).insert(Alive);
+        let offset = view.iter().next().unwrap().offset;
+        for tile in hovering.iter() {
+            let pos = tile.as_ivec2() + offset;
+            if let Some(grid) = universe.rules().grid() {
+                if !grid.contains(pos) {
+                    continue;
+                }
+            }
+            // Cycle through every state, which just toggles alive and dead for Life.
+            // Continuous cells are too fine grained for that, so they're toggled
+            // between dead and fully alive.
+            let states = universe.rules().states();
+            let state = if !universe.rules().is_continuous() {
+                (universe.state(pos) + 1) % states
+            } else if universe.state(pos) == 0 {
+                states - 1
+            } else {
+                0
+            };
+            universe.set_state(pos, state);
         }
     }
 }
@@ -111,12 +178,15 @@ fn main() {
         .add_plugin(view::ViewPlugin)
         .add_plugin(board::BoardPlugin)
         .add_plugin(render::RenderPlugin)
+        .add_plugin(message::MessagePlugin)
         .add_plugin(board_asset::BoardAssetPlugin)
+        .add_plugin(save::SavePlugin)
+        .add_plugin(selection::SelectionPlugin)
         .insert_resource(PauseTimer(Timer::from_seconds(0.2, true), false))
         //.add_startup_system(after_spawn.after(spawn_system))
-        .add_system_set(SystemSet::on_enter(GamePlaying::Paused).with_system(intital_board_setup))
         .add_system_set(
             SystemSet::on_update(GamePlaying::Paused)
+                .with_system(intital_board_setup)
                 .with_system(board_click)
                 .with_system(hoverable::hoverable),
         )
@@ -0,0 +1,281 @@
+//! [Block cellular automata](https://en.wikipedia.org/wiki/Block_cellular_automaton)
+//! on the Margolus neighbourhood, where the universe is split into 2x2 blocks that
+//! each change as a whole, and the blocks move one cell diagonally every generation.
+//!
+//! Rules are a table of what each block becomes, written like MCell does,
+//! `MS,D0;8;4;3;2;5;9;7;1;6;10;11;12;13;14;15`, or one of the named ones.
+
+use std::fmt;
+
+use bevy::math::IVec2;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::rules::RuleParseError;
+
+/// The cells of a block from the bottom left, each one's bit in the block is
+/// `1 << i`: the top left, top right, bottom left and bottom right.
+const CORNERS: [(i32, i32); 4] = [(0, 1), (1, 1), (0, 0), (1, 0)];
+
+/// A full block, every cell alive.
+const FULL: u8 = 0b1111;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRule {
+    /// What each block becomes, indexed by a bit per alive cell in the order of `CORNERS`.
+    pub table: [u8; 16],
+    /// The tables used on even and odd generations. Rules that fill empty blocks
+    /// are run inverted every other generation, so empty space stays empty.
+    tables: [[u8; 16]; 2],
+}
+
+impl BlockRule {
+    /// Blocks with two alive cells stay the same, every other block is inverted,
+    /// and ones that had three alive cells are turned upside down too.
+    pub const CRITTERS: [u8; 16] = [15, 14, 13, 3, 11, 5, 6, 1, 7, 9, 10, 2, 12, 4, 8, 0];
+    /// Empty and full blocks are inverted, every other block stays the same.
+    pub const TRON: [u8; 16] = [15, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 0];
+    /// Fredkin's billiard-ball machine: lone cells move across their block and two
+    /// that meet head on bounce off sideways.
+    pub const BILLIARD_BALLS: [u8; 16] = [0, 8, 4, 3, 2, 5, 9, 7, 1, 6, 10, 11, 12, 13, 14, 15];
+
+    /// The names of the rules above, as they're parsed and formatted.
+    const NAMED: [(&'static str, [u8; 16]); 3] = [
+        ("Critters", BlockRule::CRITTERS),
+        ("Tron", BlockRule::TRON),
+        ("BBM", BlockRule::BILLIARD_BALLS),
+    ];
+
+    pub fn new(table: [u8; 16]) -> Result<BlockRule, RuleParseError> {
+        if let Some(&block) = table.iter().find(|&&block| block > FULL) {
+            return Err(RuleParseError::InvalidParameter(block.to_string()));
+        }
+        let tables = match (table[0], table[FULL as usize]) {
+            (0, _) => [table; 2],
+            // Empty blocks fill and full ones empty, so the universe is stored
+            // inverted after even generations and put back after odd ones.
+            (FULL, 0) => [
+                table.map(|block| block ^ FULL),
+                std::array::from_fn(|block| table[block ^ FULL as usize]),
+            ],
+            _ => {
+                return Err(RuleParseError::Unsupported(
+                    "block rules where empty blocks fill but full ones don't empty",
+                ))
+            }
+        };
+        Ok(BlockRule { table, tables })
+    }
+
+    /// Whether `rule` looks like a block rule, a table or one of the names.
+    pub(crate) fn matches(rule: &str) -> bool {
+        let prefix = rule.get(..4).unwrap_or_default();
+        prefix.eq_ignore_ascii_case("MS,D")
+            || BlockRule::NAMED
+                .iter()
+                .any(|(name, _)| name.eq_ignore_ascii_case(rule))
+    }
+}
+
+impl fmt::Display for BlockRule {
+    /// Formats the rule by name if it has one, otherwise as its table.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((name, _)) = BlockRule::NAMED.iter().find(|(_, t)| *t == self.table) {
+            return write!(f, "{}", name);
+        }
+        let blocks = self.table.map(|block| block.to_string());
+        write!(f, "MS,D{}", blocks.join(";"))
+    }
+}
+
+impl std::str::FromStr for BlockRule {
+    type Err = RuleParseError;
+
+    /// Parses a name ignoring case, or `MS,D` and the 16 blocks separated by `;`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some((_, table)) = BlockRule::NAMED
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        {
+            return BlockRule::new(*table);
+        }
+        let blocks = s.get(4..).ok_or(RuleParseError::Malformed)?;
+        let blocks = blocks
+            .split(';')
+            .map(|block| {
+                let block = block.trim();
+                block
+                    .parse()
+                    .map_err(|_| RuleParseError::InvalidParameter(block.to_string()))
+            })
+            .collect::<Result<Vec<u8>, _>>()?;
+        // There has to be a block for every arrangement of the four cells.
+        let table = blocks
+            .try_into()
+            .map_err(|_| RuleParseError::InvalidParameter(s.to_string()))?;
+        BlockRule::new(table)
+    }
+}
+
+/// Replaces every block with an alive cell in it by what the rule turns it into.
+///
+/// Blocks start at even coordinates, or odd ones if `odd`, which alternates every
+/// generation starting from even when the rule is set.
+pub(crate) fn step(cells: &HashMap<IVec2, u8>, rule: &BlockRule, odd: bool) -> HashMap<IVec2, u8> {
+    let phase = odd as i32;
+    let table = &rule.tables[phase as usize];
+    let corner = |pos: IVec2| {
+        let start = |x: i32| (x - phase).div_euclid(2) * 2 + phase;
+        IVec2::new(start(pos.x), start(pos.y))
+    };
+    let blocks = cells
+        .iter()
+        .filter(|(_, &state)| state != 0)
+        .map(|(&pos, _)| corner(pos))
+        .collect::<HashSet<_>>();
+
+    let alive = |pos: IVec2| cells.get(&pos).is_some_and(|&state| state != 0);
+    blocks
+        .into_iter()
+        .flat_map(|corner| {
+            let block = CORNERS.iter().enumerate().fold(0, |acc, (i, &offset)| {
+                acc | (alive(corner + IVec2::from(offset)) as usize) << i
+            });
+            let next = table[block];
+            CORNERS
+                .iter()
+                .enumerate()
+                .filter(move |&(i, _)| next >> i & 1 != 0)
+                .map(move |(_, &offset)| (corner + IVec2::from(offset), 1))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::rules::GameRules;
+    use crate::stochastic::Randomness;
+    use crate::universe::Universe;
+
+    fn soup(size: i32) -> HashMap<IVec2, u8> {
+        let mut rng = StdRng::seed_from_u64(5);
+        (0..size * size)
+            .map(|i| IVec2::new(i % size - size / 2, i / size - size / 2))
+            .filter(|_| rng.gen_bool(0.4))
+            .map(|pos| (pos, 1))
+            .collect()
+    }
+
+    fn sorted(cells: &HashMap<IVec2, u8>) -> Vec<(i32, i32)> {
+        let mut cells = cells.keys().map(|pos| (pos.x, pos.y)).collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    fn alive(universe: &Universe) -> HashMap<IVec2, u8> {
+        universe.iter_alive().map(|pos| (pos, 1)).collect()
+    }
+
+    /// The rule that undoes each generation of `rule`.
+    fn inverse(rule: &BlockRule) -> BlockRule {
+        let tables = rule.tables.map(|table| {
+            let mut inverse = [0; 16];
+            for (block, &next) in table.iter().enumerate() {
+                inverse[next as usize] = block as u8;
+            }
+            inverse
+        });
+        BlockRule {
+            table: tables[0],
+            tables,
+        }
+    }
+
+    #[test]
+    fn critters_runs_backwards() {
+        let rule = "Critters".parse::<BlockRule>().unwrap();
+        let start = soup(20);
+        let mut cells = start.clone();
+        for generation in 0..40 {
+            cells = step(&cells, &rule, generation % 2 == 1);
+        }
+        assert_ne!(sorted(&cells), sorted(&start));
+        let inverse = inverse(&rule);
+        for generation in (0..40).rev() {
+            cells = step(&cells, &inverse, generation % 2 == 1);
+        }
+        assert_eq!(sorted(&cells), sorted(&start));
+    }
+
+    #[test]
+    fn billiard_balls_move_diagonally() {
+        let rule = BlockRule::new(BlockRule::BILLIARD_BALLS).unwrap();
+        let mut cells = HashMap::from_iter([(IVec2::new(0, 0), 1)]);
+        for generation in 0..4 {
+            cells = step(&cells, &rule, generation % 2 == 1);
+        }
+        assert_eq!(sorted(&cells), [(4, 4)]);
+    }
+
+    #[test]
+    fn phase_restarts_with_new_rules() {
+        let mut randomness = Randomness::new(0);
+        let mut universe = Universe::new(GameRules::conway());
+        universe.set_rules("Critters".parse().unwrap());
+        for (pos, _) in soup(10) {
+            universe.set(pos, true);
+        }
+        let mut again = universe.clone();
+        universe.step(&mut randomness);
+        // Setting the same rule keeps the blocks where they were.
+        again.step(&mut randomness);
+        again.set_rules("Critters".parse().unwrap());
+        universe.step(&mut randomness);
+        again.step(&mut randomness);
+        assert_eq!(sorted(&alive(&universe)), sorted(&alive(&again)));
+
+        // Changing rule starts from even blocks again.
+        universe.set_rules("Tron".parse().unwrap());
+        let mut fresh = Universe::new(GameRules::conway());
+        fresh.set_rules("Tron".parse().unwrap());
+        for pos in universe.iter_alive() {
+            fresh.set(pos, true);
+        }
+        universe.step(&mut randomness);
+        fresh.step(&mut randomness);
+        assert_eq!(sorted(&alive(&universe)), sorted(&alive(&fresh)));
+    }
+
+    #[test]
+    fn names_and_tables() {
+        assert_eq!(
+            "MS,D15;14;13;3;11;5;6;1;7;9;10;2;12;4;8;0"
+                .parse::<BlockRule>()
+                .unwrap()
+                .to_string(),
+            "Critters"
+        );
+        assert_eq!(
+            "bbm".parse::<BlockRule>().unwrap().table,
+            BlockRule::BILLIARD_BALLS
+        );
+        let table = "MS,D0;8;4;3;2;5;9;7;1;6;10;11;12;13;15;14";
+        assert_eq!(table.parse::<BlockRule>().unwrap().to_string(), table);
+        for rule in [
+            "MS,D0;1;2",
+            "MS,D0;1;2;3;4;5;6;7;8;9;10;11;12;13;14;16",
+            "MS,D15;1;2;3;4;5;6;7;8;9;10;11;12;13;14;15",
+            "MS,D0;1;2;3;4;5;6;7;8;9;10;11;12;13;14;x",
+        ] {
+            assert!(rule.parse::<BlockRule>().is_err(), "{}", rule);
+        }
+        // Blocks have to tile a grid.
+        assert_eq!(
+            "Critters:T9,8".parse::<GameRules>(),
+            Err(RuleParseError::InvalidGrid("T9,8".to_string()))
+        );
+    }
+}
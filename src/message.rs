@@ -0,0 +1,56 @@
+use bevy::prelude::*;
+
+/// Puts a line of text on screen, replacing whatever was shown before.
+pub struct ShowMessage(pub String);
+
+#[derive(Component)]
+struct MessageText;
+
+const FONT: &str = "fonts/DejaVuSansMono.ttf";
+
+fn spawn_message(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn_bundle(UiCameraBundle::default());
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(8.),
+                    left: Val::Px(8.),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load(FONT),
+                    font_size: 16.,
+                    color: Color::RED,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(MessageText);
+}
+
+fn show_messages(
+    mut messages: EventReader<ShowMessage>,
+    mut text: Query<&mut Text, With<MessageText>>,
+) {
+    if let Some(ShowMessage(message)) = messages.iter().last() {
+        for mut text in text.iter_mut() {
+            text.sections[0].value = message.clone();
+        }
+    }
+}
+
+pub(crate) struct MessagePlugin;
+impl Plugin for MessagePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ShowMessage>()
+            .add_startup_system(spawn_message)
+            .add_system(show_messages);
+    }
+}
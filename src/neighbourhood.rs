@@ -0,0 +1,174 @@
+//! The cells around a cell that count as its neighbours.
+
+use std::fmt;
+
+use crate::rules::RuleParseError;
+
+/// The largest custom neighbourhood is this many cells either side of the centre.
+const MAX_CUSTOM_RADIUS: i32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Neighbourhood {
+    /// The eight cells around one.
+    Moore,
+    /// The four cells that share an edge with one, the `V` suffix.
+    VonNeumann,
+    /// A hexagonal grid drawn on a square one, Moore without the top right and
+    /// bottom left corners like in Golly, the `H` suffix.
+    Hexagonal,
+    /// Any cells up to two away, as offsets with y up.
+    Custom(Vec<(i32, i32)>),
+}
+
+impl Neighbourhood {
+    /// The offsets of the neighbours with y up, each neighbourhood passed to
+    /// `GameRules::next_state` has its bit `i` set if the cell at offset `i` is alive.
+    pub fn offsets(&self) -> &[(i32, i32)] {
+        match self {
+            Neighbourhood::Moore => &[
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ],
+            Neighbourhood::VonNeumann => &[(0, -1), (-1, 0), (1, 0), (0, 1)],
+            Neighbourhood::Hexagonal => &[(0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1)],
+            Neighbourhood::Custom(offsets) => offsets,
+        }
+    }
+
+    /// Splits the `V`, `H` or `@...` suffix off the end of a rulestring.
+    pub(crate) fn split_suffix(rule: &str) -> Result<(&str, Neighbourhood), RuleParseError> {
+        if let Some((rule, mask)) = rule.split_once('@') {
+            return Ok((rule, Neighbourhood::parse_custom(mask)?));
+        }
+        Ok(match rule.strip_suffix(['V', 'v']) {
+            Some(rule) => (rule, Neighbourhood::VonNeumann),
+            None => match rule.strip_suffix(['H', 'h']) {
+                Some(rule) => (rule, Neighbourhood::Hexagonal),
+                None => (rule, Neighbourhood::Moore),
+            },
+        })
+    }
+
+    /// Reads a custom neighbourhood, hex digits with a bit per cell of the largest
+    /// square that fits, row by row from the top left and the most significant
+    /// bit first. The bit for the cell itself is ignored.
+    fn parse_custom(mask: &str) -> Result<Neighbourhood, RuleParseError> {
+        let bits = mask
+            .chars()
+            .map(|c| c.to_digit(16))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| RuleParseError::InvalidNeighbourhood(mask.to_string()))?
+            .into_iter()
+            .flat_map(|digit| (0..4).rev().map(move |bit| digit & 1 << bit != 0))
+            .collect::<Vec<_>>();
+        let radius = (1..=MAX_CUSTOM_RADIUS)
+            .rev()
+            .find(|r| ((2 * r + 1) * (2 * r + 1)) as usize <= bits.len())
+            .ok_or_else(|| RuleParseError::InvalidNeighbourhood(mask.to_string()))?;
+
+        let side = 2 * radius + 1;
+        let offsets = (0..side * side)
+            .filter(|&i| bits[i as usize])
+            .map(|i| (i % side - radius, radius - i / side))
+            .filter(|&offset| offset != (0, 0))
+            .collect::<Vec<_>>();
+        if offsets.is_empty() {
+            return Err(RuleParseError::InvalidNeighbourhood(mask.to_string()));
+        }
+        Ok(Neighbourhood::Custom(offsets))
+    }
+}
+
+impl fmt::Display for Neighbourhood {
+    /// Formats the neighbourhood as the suffix of a rulestring.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Neighbourhood::Moore => Ok(()),
+            Neighbourhood::VonNeumann => write!(f, "V"),
+            Neighbourhood::Hexagonal => write!(f, "H"),
+            Neighbourhood::Custom(offsets) => {
+                let radius = offsets
+                    .iter()
+                    .map(|&(x, y)| x.abs().max(y.abs()))
+                    .max()
+                    .unwrap_or(1)
+                    .max(1);
+                let side = 2 * radius + 1;
+                let mut bits = (0..side * side)
+                    .map(|i| offsets.contains(&(i % side - radius, radius - i / side)))
+                    .collect::<Vec<_>>();
+                bits.resize(bits.len().div_ceil(4) * 4, false);
+
+                write!(f, "@")?;
+                for digit in bits.chunks(4) {
+                    let digit = digit.iter().fold(0, |acc, &bit| acc << 1 | bit as u32);
+                    write!(f, "{:x}", digit)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::GameRules;
+
+    #[test]
+    fn suffixes() {
+        for (rule, neighbourhood) in [
+            ("B3/S23", Neighbourhood::Moore),
+            ("B2/S34H", Neighbourhood::Hexagonal),
+            ("B1/S12v", Neighbourhood::VonNeumann),
+        ] {
+            let (rest, split) = Neighbourhood::split_suffix(rule).unwrap();
+            assert_eq!(split, neighbourhood, "{}", rule);
+            assert_eq!(format!("{}{}", rest, split), rule.to_uppercase());
+        }
+
+        // Every cell of the square but the middle one is the Moore neighbourhood.
+        let (_, custom) = Neighbourhood::split_suffix("B3/S23@f78").unwrap();
+        let mut offsets = custom.offsets().to_vec();
+        offsets.sort_unstable();
+        let mut moore = Neighbourhood::Moore.offsets().to_vec();
+        moore.sort_unstable();
+        assert_eq!(offsets, moore);
+
+        // Bigger masks reach two cells away.
+        let (_, custom) = Neighbourhood::split_suffix("B3/S23@1ffbbff").unwrap();
+        assert!(custom.offsets().contains(&(2, -2)));
+        let written = format!("B3/S23{}", custom);
+        assert_eq!(Neighbourhood::split_suffix(&written).unwrap().1, custom);
+
+        for mask in ["B3/S23@", "B3/S23@zz", "B3/S23@000"] {
+            assert!(Neighbourhood::split_suffix(mask).is_err(), "{}", mask);
+        }
+    }
+
+    #[test]
+    fn only_neighbours_count() {
+        let rules = "B1/SV".parse::<GameRules>().unwrap();
+        for (i, &offset) in Neighbourhood::Moore.offsets().iter().enumerate() {
+            let next = rules.next_state(0, neighbours_at(&rules, offset));
+            let orthogonal = offset.0 == 0 || offset.1 == 0;
+            assert_eq!(next == 1, orthogonal, "neighbour {} at {:?}", i, offset);
+        }
+    }
+
+    /// The neighbour bits of `rules` with only the cell at `offset` alive.
+    fn neighbours_at(rules: &GameRules, offset: (i32, i32)) -> u32 {
+        rules
+            .neighbourhood()
+            .offsets()
+            .iter()
+            .position(|&o| o == offset)
+            .map_or(0, |i| 1 << i)
+    }
+}
@@ -1,18 +1,9 @@
-use std::sync::{Arc, Mutex};
-
-use bevy::{
-    prelude::*,
-    tasks::ComputeTaskPool,
-    utils::{HashMap, HashSet},
-    window::WindowResized,
-};
-
-use crate::{
-    board::{Board, Update},
-    hoverable::Hoverable,
-    view::View,
-    Alive,
-};
+use bevy::{prelude::*, tasks::ComputeTaskPool};
+
+use automata::rules::GameRules;
+use automata::universe::Universe;
+
+use crate::{hoverable::Hoverable, selection::Selection, view::View};
 
 #[derive(Component, Deref, PartialEq, Eq)]
 pub struct TilePosition(UVec2);
@@ -82,37 +73,139 @@ fn cell_to_pos(
     )
 }
 
+/// How the values of continuous cells are coloured, `G` switches between them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Colormap {
+    /// Dark blue through cyan and yellow to red.
+    #[default]
+    Heat,
+    Greyscale,
+}
+
+impl Colormap {
+    /// The colours `Heat` moves between, evenly spaced from a value of 0 to 1.
+    const HEAT: [[f32; 3]; 5] = [
+        [0., 0., 0.],
+        [0.1, 0.1, 0.6],
+        [0., 0.6, 0.8],
+        [0.9, 0.9, 0.2],
+        [1., 0.3, 0.1],
+    ];
+
+    fn color(self, value: f32) -> Color {
+        let value = value.clamp(0., 1.);
+        match self {
+            Colormap::Greyscale => Color::rgb(value, value, value),
+            Colormap::Heat => {
+                let position = value * (Colormap::HEAT.len() - 1) as f32;
+                let i = (position as usize).min(Colormap::HEAT.len() - 2);
+                let (from, to, t) = (
+                    Colormap::HEAT[i],
+                    Colormap::HEAT[i + 1],
+                    position - i as f32,
+                );
+                let mix = |channel: usize| from[channel] + (to[channel] - from[channel]) * t;
+                Color::rgb(mix(0), mix(1), mix(2))
+            }
+        }
+    }
+}
+
+fn switch_colormap(keyboard_input: Res<Input<KeyCode>>, mut colormap: ResMut<Colormap>) {
+    if keyboard_input.just_pressed(KeyCode::G) {
+        *colormap = match *colormap {
+            Colormap::Heat => Colormap::Greyscale,
+            Colormap::Greyscale => Colormap::Heat,
+        };
+    }
+}
+
+/// Rules with a palette use it, otherwise alive cells are white and dead ones
+/// black, and the dying states of Generations rules fade from orange to dark red.
+fn state_color(state: u8, rules: &GameRules) -> Color {
+    if let Some(&[r, g, b]) = rules
+        .palette()
+        .and_then(|palette| palette.get(state as usize))
+    {
+        return Color::rgb_u8(r, g, b);
+    }
+    let states = rules.states();
+    match state {
+        0 => Color::rgb(0., 0., 0.),
+        1 => Color::rgb(1., 1., 1.),
+        _ => {
+            let fade = 1. - (state - 2) as f32 / (states - 1) as f32;
+            Color::rgb(0.3 + 0.7 * fade, 0.5 * fade, 0.1 * fade)
+        }
+    }
+}
+
 pub fn update_colors(
-    //In(should_update): In<bool>,
     pool: Res<ComputeTaskPool>,
-    board: Res<Board>,
+    universe: Res<Universe>,
+    selection: Res<Selection>,
+    colormap: Res<Colormap>,
     view: Query<&View>,
     mut sprites: Query<(&mut Sprite, &TilePosition)>,
-    board_tiles: Query<&Alive>,
 ) {
-    if board_tiles.iter().count() == 0 {
-        return;
-    }
+    let offset = view.iter().next().unwrap().offset;
+    let grid = universe.rules().grid();
+    let continuous = universe.rules().is_continuous();
     sprites.par_for_each_mut(&pool, 64, |(mut sprite, pos)| {
-        let pos = IVec2::new(pos.0.x as i32, pos.0.y as i32);
-        let board_pos = pos + view.iter().next().unwrap().offset;
-        sprite.color = if let Some(entity) = board.get(board_pos) {
-            if board_tiles.get(*entity).is_ok() {
-                Color::rgb(1., 1., 1.)
-            } else {
-                Color::rgb(0., 0., 0.)
-            }
+        let board_pos = pos.as_ivec2() + offset;
+        let color = if continuous {
+            colormap.color(universe.value(board_pos))
         } else {
-            Color::rgb(0., 0., 0.)
-        }
+            state_color(universe.state(board_pos), universe.rules())
+        };
+        sprite.color = if grid.is_some_and(|grid| !grid.contains(board_pos)) {
+            // Shade everything past the edges of a bounded grid so they can be seen.
+            Color::rgb(0.15, 0.15, 0.2)
+        } else if selection.contains(board_pos) {
+            // Tint selected cells blue, white becomes light blue and black dark blue.
+            Color::rgb(
+                0.1 + 0.5 * color.r(),
+                0.15 + 0.65 * color.g(),
+                0.3 + 0.7 * color.b(),
+            )
+        } else {
+            color
+        };
     });
 }
 
+/// Shows the generation the universe is currently at in the window title.
+fn update_title(universe: Res<Universe>, mut windows: ResMut<Windows>) {
+    if !universe.is_changed() {
+        return;
+    }
+    // With an alive background the cells that are stored are the dead ones.
+    let population = match (universe.background(), universe.rules().grid()) {
+        (false, _) => universe.population().to_string(),
+        (true, Some(grid)) if grid.width > 0 && grid.height > 0 => {
+            (grid.width as u64 * grid.height as u64 - universe.population()).to_string()
+        }
+        (true, _) => "infinite".to_string(),
+    };
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_title(format!(
+            "{} - generation {} (+2^{}) - population {}",
+            universe.rules(),
+            universe.generation(),
+            universe.step_exponent(),
+            population
+        ));
+    }
+}
+
 pub(crate) struct RenderPlugin;
 impl Plugin for RenderPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(RenderTimer(Timer::from_seconds(0.01, true)))
+            .init_resource::<Colormap>()
             .add_startup_system(spawn_tiles)
-            .add_system(update_colors);
+            .add_system(switch_colormap)
+            .add_system(update_colors)
+            .add_system(update_title);
     }
 }
@@ -0,0 +1,796 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::automaton::Automaton;
+use crate::bounded::{BoundedGrid, Topology};
+use crate::continuous::{self, ContinuousRule};
+use crate::hensel;
+use crate::ltl::LargerThanLife;
+use crate::margolus::BlockRule;
+use crate::neighbourhood::Neighbourhood;
+use crate::ruletable::RuleTable;
+use crate::stochastic::Chances;
+use crate::wolfram::WolframRule;
+
+/// Rules without a neighbourhood of their own use the Moore neighbourhood.
+static MOORE: Neighbourhood = Neighbourhood::Moore;
+
+/// The rules used to decide the next state of a cell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameRules {
+    /// Any outer-totalistic rule, each bit `n` of `birth`/`survive` is set if a cell
+    /// with `n` alive neighbours is born/survives.
+    LifeLike {
+        birth: u16,
+        survive: u16,
+        neighbourhood: Neighbourhood,
+    },
+    /// A Life-like rule where cells that don't survive go through `states - 2`
+    /// dying states, which can't be born into or counted as neighbours, before
+    /// they're dead.
+    Generations {
+        birth: u16,
+        survive: u16,
+        states: u8,
+        neighbourhood: Neighbourhood,
+    },
+    /// An isotropic non-totalistic rule, where whether a cell is born or survives
+    /// depends on the arrangement of its alive neighbours, not just how many
+    /// there are.
+    Isotropic {
+        birth: NeighbourhoodSet,
+        survive: NeighbourhoodSet,
+    },
+    LargerThanLife(LargerThanLife),
+    /// An automaton with fixed rules known by its name, like WireWorld.
+    Named(Automaton),
+    /// A rule read from a Golly `.rule` file.
+    Table(Arc<RuleTable>),
+    /// A one dimensional rule, where each generation is a new row.
+    Wolfram(WolframRule),
+    /// A block rule, where 2x2 blocks change as a whole rather than each cell.
+    Margolus(BlockRule),
+    /// A rule where cells are real numbers rather than states, on a bounded grid.
+    Continuous(ContinuousRule),
+    /// Another rule where births, survivals and noise happen by chance.
+    Stochastic {
+        rules: Box<GameRules>,
+        chances: Chances,
+    },
+    /// Another rule on a finite grid rather than an infinite one.
+    Bounded {
+        rules: Box<GameRules>,
+        grid: BoundedGrid,
+    },
+}
+
+/// A set of arrangements of alive cells in the Moore neighbourhood, each one a
+/// byte with a bit per neighbour from the top left to the bottom right.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NeighbourhoodSet([u64; 4]);
+
+impl NeighbourhoodSet {
+    pub fn contains(&self, neighbours: u8) -> bool {
+        self.0[neighbours as usize / 64] & 1 << (neighbours % 64) != 0
+    }
+
+    pub fn insert(&mut self, neighbours: u8) {
+        self.0[neighbours as usize / 64] |= 1 << (neighbours % 64);
+    }
+}
+
+impl GameRules {
+    /// Conway's Game of Life, `B3/S23`.
+    pub fn conway() -> Self {
+        GameRules::LifeLike {
+            birth: 1 << 3,
+            survive: 1 << 2 | 1 << 3,
+            neighbourhood: Neighbourhood::Moore,
+        }
+    }
+
+    /// The state a cell in `state` moves to when `neighbours` has a bit set for each
+    /// of its neighbours in state `1`, in the order of `Neighbourhood::offsets`.
+    pub fn next_state(&self, state: u8, neighbours: u32) -> u8 {
+        let (born, survives) = match self {
+            GameRules::Isotropic { birth, survive } => {
+                let neighbours = neighbours as u8;
+                (birth.contains(neighbours), survive.contains(neighbours))
+            }
+            GameRules::LargerThanLife(rule) => {
+                return rule.next_state(state, neighbours.count_ones());
+            }
+            GameRules::Named(automaton) => {
+                return automaton.next_state(state, neighbours.count_ones());
+            }
+            GameRules::Bounded { rules, .. } | GameRules::Stochastic { rules, .. } => {
+                return rules.next_state(state, neighbours);
+            }
+            // Blocks aren't decided a cell at a time, the sparse engine steps them,
+            // and continuous rules need the values of cells rather than states.
+            GameRules::Margolus(_) | GameRules::Continuous(_) => return state,
+            GameRules::Wolfram(rule) => {
+                // Only neighbours in state `1` are known here, the sparse engine
+                // gives the rule the whole row instead.
+                let radius = rule.radius as usize;
+                let bit = |i: usize| (neighbours >> i & 1) as u8;
+                let row = (0..radius)
+                    .map(bit)
+                    .chain(std::iter::once(state))
+                    .chain((radius..2 * radius).map(bit))
+                    .collect::<Vec<_>>();
+                return rule.next_state(&row);
+            }
+            GameRules::Table(table) => {
+                // Only neighbours in state `1` are known here, the sparse engine
+                // gives tables the state of every neighbour instead.
+                let cells = std::iter::once(state)
+                    .chain(
+                        (0..table.neighbourhood.offsets().len())
+                            .map(|i| (neighbours >> i & 1) as u8),
+                    )
+                    .collect::<Vec<_>>();
+                return table.next_state(&cells);
+            }
+            _ => {
+                let (birth, survive) = self.masks().unwrap_or_default();
+                let count = neighbours.count_ones();
+                let has = |mask: u16| mask.checked_shr(count).unwrap_or(0) & 1 != 0;
+                (has(birth), has(survive))
+            }
+        };
+        match state {
+            0 => born as u8,
+            1 if survives => 1,
+            _ if state.saturating_add(1) < self.states() => state + 1,
+            _ => 0,
+        }
+    }
+
+    /// The grid the rule runs on, if it isn't infinite.
+    pub fn grid(&self) -> Option<&BoundedGrid> {
+        match self {
+            GameRules::Bounded { grid, .. } => Some(grid),
+            GameRules::Continuous(_) => Some(&continuous::DEFAULT_GRID),
+            _ => None,
+        }
+    }
+
+    /// The rule without a bounded grid or chances, the one that decides what
+    /// cells become.
+    pub fn base(&self) -> &GameRules {
+        match self {
+            GameRules::Bounded { rules, .. } | GameRules::Stochastic { rules, .. } => rules.base(),
+            rules => rules,
+        }
+    }
+
+    /// Whether cells are real numbers rather than states.
+    pub fn is_continuous(&self) -> bool {
+        matches!(self.base(), GameRules::Continuous(_))
+    }
+
+    /// Whether dead cells with no alive neighbours are born, `B0`, which turns on
+    /// every cell away from the pattern.
+    pub fn has_b0(&self) -> bool {
+        match self {
+            GameRules::Isotropic { birth, .. } => birth.contains(0),
+            GameRules::Bounded { rules, .. } | GameRules::Stochastic { rules, .. } => {
+                rules.has_b0()
+            }
+            _ => self.masks().is_some_and(|(birth, _)| birth & 1 != 0),
+        }
+    }
+
+    /// Whether the rule can be stepped by `relative_to` while every cell away from
+    /// the pattern is alive.
+    pub(crate) fn runs_inverted(&self) -> bool {
+        match self {
+            // Cells past the edges of a plane stay dead whatever the rule does.
+            GameRules::Bounded { rules, grid } => {
+                grid.topology != Topology::Plane && rules.runs_inverted()
+            }
+            GameRules::LifeLike { .. } | GameRules::Isotropic { .. } => {
+                self.neighbourhood().offsets().len() < u16::BITS as usize
+            }
+            _ => false,
+        }
+    }
+
+    /// The rule that steps the complement of a pattern, where every alive cell is
+    /// dead and every dead one alive, the way this one steps the pattern. Only
+    /// rules that can run inverted have one, and only if its counts fit in digits.
+    pub fn complement(&self) -> Option<GameRules> {
+        match self {
+            GameRules::Bounded { rules, grid } => Some(GameRules::Bounded {
+                rules: Box::new(rules.complement()?),
+                grid: grid.clone(),
+            }),
+            GameRules::Isotropic { birth, survive } => {
+                let mut complement = [NeighbourhoodSet::default(); 2];
+                for neighbours in 0..=u8::MAX {
+                    // A cell of the complement is born where the real one dies.
+                    for (set, rule) in complement.iter_mut().zip([survive, birth]) {
+                        if !rule.contains(!neighbours) {
+                            set.insert(neighbours);
+                        }
+                    }
+                }
+                let [birth, survive] = complement;
+                Some(GameRules::Isotropic { birth, survive })
+            }
+            GameRules::LifeLike { .. } => {
+                let (birth, survive) = self.masks()?;
+                let total = self.neighbourhood().offsets().len() as u32;
+                let mut complement = [0u16; 2];
+                for count in 0..=total {
+                    for (mask, rule) in complement.iter_mut().zip([survive, birth]) {
+                        if rule.checked_shr(total - count).unwrap_or(0) & 1 == 0 {
+                            // Counts are written as single digits.
+                            if count > 9 {
+                                return None;
+                            }
+                            *mask |= 1 << count;
+                        }
+                    }
+                }
+                let [birth, survive] = complement;
+                let neighbourhood = self.neighbourhood().clone();
+                Some(GameRules::LifeLike {
+                    birth,
+                    survive,
+                    neighbourhood,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The rule that steps cells stored relative to `background`, whether every
+    /// cell away from the pattern is alive, and what the background becomes.
+    ///
+    /// An alive background can't be stored, so like Golly the cells are stored
+    /// inverted while it's alive, and the rule returned takes them from how they're
+    /// stored to how the next generation is. It never has `B0` itself, so only
+    /// cells near the pattern change. Rules with `B0` but not `S8` strobe, the
+    /// background turns on and off every generation.
+    pub(crate) fn relative_to(&self, background: bool) -> (Cow<'_, GameRules>, bool) {
+        if !background && !self.has_b0() {
+            return (Cow::Borrowed(self), false);
+        }
+        match self {
+            GameRules::Bounded { rules, grid } => {
+                let (rules, next) = rules.relative_to(background);
+                let rules = Box::new(rules.into_owned());
+                let grid = grid.clone();
+                (Cow::Owned(GameRules::Bounded { rules, grid }), next)
+            }
+            GameRules::Isotropic { birth, survive } => {
+                // A stored dead cell is really in the background's state.
+                let (from_dead, from_alive) = if background {
+                    (survive, birth)
+                } else {
+                    (birth, survive)
+                };
+                let next = if background {
+                    survive.contains(u8::MAX)
+                } else {
+                    birth.contains(0)
+                };
+                let mut stored = [NeighbourhoodSet::default(); 2];
+                for neighbours in 0..=u8::MAX {
+                    let real = if background { !neighbours } else { neighbours };
+                    for (set, rule) in stored.iter_mut().zip([from_dead, from_alive]) {
+                        if rule.contains(real) != next {
+                            set.insert(neighbours);
+                        }
+                    }
+                }
+                let [birth, survive] = stored;
+                (Cow::Owned(GameRules::Isotropic { birth, survive }), next)
+            }
+            _ => {
+                let (birth, survive) = self.masks().unwrap_or_default();
+                let (from_dead, from_alive) = if background {
+                    (survive, birth)
+                } else {
+                    (birth, survive)
+                };
+                let total = self.neighbourhood().offsets().len() as u32;
+                let has = |mask: u16, count: u32| mask.checked_shr(count).unwrap_or(0) & 1 != 0;
+                let next = if background {
+                    has(survive, total)
+                } else {
+                    has(birth, 0)
+                };
+                let mut stored = [0; 2];
+                for count in 0..=total.min(u16::BITS - 1) {
+                    let real = if background { total - count } else { count };
+                    for (mask, rule) in stored.iter_mut().zip([from_dead, from_alive]) {
+                        *mask |= ((has(rule, real) != next) as u16) << count;
+                    }
+                }
+                let [birth, survive] = stored;
+                let neighbourhood = self.neighbourhood().clone();
+                let rules = GameRules::LifeLike {
+                    birth,
+                    survive,
+                    neighbourhood,
+                };
+                (Cow::Owned(rules), next)
+            }
+        }
+    }
+
+    /// The `(birth, survive)` neighbour counts of the rule, bit `n` is set if a cell
+    /// with `n` alive neighbours is born/survives. Rules that depend on more than
+    /// the count don't have them.
+    pub fn masks(&self) -> Option<(u16, u16)> {
+        match self {
+            GameRules::LifeLike { birth, survive, .. }
+            | GameRules::Generations { birth, survive, .. } => Some((*birth, *survive)),
+            GameRules::Isotropic { .. }
+            | GameRules::LargerThanLife(_)
+            | GameRules::Named(_)
+            | GameRules::Table(_)
+            | GameRules::Wolfram(_)
+            | GameRules::Margolus(_)
+            | GameRules::Continuous(_)
+            | GameRules::Stochastic { .. }
+            | GameRules::Bounded { .. } => None,
+        }
+    }
+
+    /// How many states a cell can be in, including dead.
+    pub fn states(&self) -> u8 {
+        match self {
+            GameRules::LifeLike { .. } | GameRules::Isotropic { .. } | GameRules::Margolus(_) => 2,
+            GameRules::Generations { states, .. } => *states,
+            GameRules::LargerThanLife(rule) => rule.states,
+            GameRules::Named(automaton) => automaton.states(),
+            GameRules::Table(table) => table.states,
+            GameRules::Wolfram(rule) => rule.states,
+            GameRules::Continuous(_) => continuous::LEVELS,
+            GameRules::Bounded { rules, .. } | GameRules::Stochastic { rules, .. } => {
+                rules.states()
+            }
+        }
+    }
+
+    /// The colour of each state as RGB, for rules that have their own.
+    pub fn palette(&self) -> Option<&[[u8; 3]]> {
+        match self {
+            GameRules::Named(automaton) => Some(automaton.palette()),
+            GameRules::Table(table) => table.palette.as_deref(),
+            GameRules::Bounded { rules, .. } | GameRules::Stochastic { rules, .. } => {
+                rules.palette()
+            }
+            _ => None,
+        }
+    }
+
+    pub fn neighbourhood(&self) -> &Neighbourhood {
+        match self {
+            GameRules::LifeLike { neighbourhood, .. }
+            | GameRules::Generations { neighbourhood, .. } => neighbourhood,
+            GameRules::LargerThanLife(rule) => &rule.neighbourhood,
+            GameRules::Table(table) => &table.neighbourhood,
+            GameRules::Wolfram(rule) => &rule.neighbourhood,
+            GameRules::Bounded { rules, .. } | GameRules::Stochastic { rules, .. } => {
+                rules.neighbourhood()
+            }
+            // A block's cells are all within the Moore neighbourhood of each other.
+            GameRules::Isotropic { .. }
+            | GameRules::Named(_)
+            | GameRules::Margolus(_)
+            | GameRules::Continuous(_) => &MOORE,
+        }
+    }
+}
+
+impl fmt::Display for GameRules {
+    /// Formats the rule in `B.../S...` notation.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (birth, survive) = match self {
+            GameRules::Isotropic { birth, survive } => {
+                return write!(f, "B{}/S{}", hensel::format(birth), hensel::format(survive));
+            }
+            GameRules::LargerThanLife(rule) => return write!(f, "{}", rule),
+            GameRules::Named(automaton) => return write!(f, "{}", automaton),
+            GameRules::Table(table) => return write!(f, "{}", table.name),
+            GameRules::Wolfram(rule) => return write!(f, "{}", rule),
+            GameRules::Margolus(rule) => return write!(f, "{}", rule),
+            GameRules::Continuous(rule) => return write!(f, "{}", rule),
+            GameRules::Stochastic { rules, chances } => return write!(f, "{}{}", rules, chances),
+            GameRules::Bounded { rules, grid } => return write!(f, "{}{}", rules, grid),
+            _ => self.masks().unwrap_or_default(),
+        };
+        let counts = |mask: u16| -> String {
+            (0..=9)
+                .filter(|n| mask & (1 << n) != 0)
+                .map(|n| char::from(b'0' + n as u8))
+                .collect()
+        };
+        write!(f, "B{}/S{}", counts(birth), counts(survive))?;
+        if let GameRules::Generations { states, .. } = self {
+            write!(f, "/C{}", states)?;
+        }
+        write!(f, "{}", self.neighbourhood())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleParseError {
+    Empty,
+    /// The rule wasn't of the form `B.../S...`, `S.../B...` or `S/B`, with an
+    /// optional `/C...` or `/...` number of states.
+    Malformed,
+    /// The same section (`B`, `S`, `C` or the `~` chances) was given twice.
+    DuplicateSection(char),
+    InvalidNeighbourCount(char),
+    /// A custom neighbourhood wasn't hex digits covering at least a 3x3 square.
+    InvalidNeighbourhood(String),
+    /// A part of a Larger than Life, one dimensional or block rule that's out of range
+    /// or couldn't be read.
+    InvalidParameter(String),
+    /// A rule that can be read but not run.
+    Unsupported(&'static str),
+    /// A bounded grid suffix that isn't `:P`, `:T` or `:K` and a size.
+    InvalidGrid(String),
+    /// A Hensel notation letter that doesn't go with the neighbour count before it.
+    InvalidLetter(u32, char),
+    /// The number of states of a Generations rule wasn't between 2 and 255.
+    InvalidStates(String),
+}
+
+impl fmt::Display for RuleParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleParseError::Empty => write!(f, "empty rulestring"),
+            RuleParseError::Malformed => {
+                write!(f, "expected a rulestring like `B3/S23` or `23/3`")
+            }
+            RuleParseError::DuplicateSection(c) => write!(f, "`{}` section given twice", c),
+            RuleParseError::InvalidNeighbourCount(c) => {
+                write!(f, "`{}` is not a neighbour count between 0 and 8", c)
+            }
+            RuleParseError::InvalidNeighbourhood(mask) => {
+                write!(f, "`@{}` is not a neighbourhood mask", mask)
+            }
+            RuleParseError::InvalidParameter(part) => {
+                write!(f, "`{}` is not a valid rule parameter", part)
+            }
+            RuleParseError::Unsupported(rules) => write!(f, "{} aren't supported", rules),
+            RuleParseError::InvalidGrid(grid) => {
+                write!(f, "`:{}` is not a bounded grid like `:T100,80`", grid)
+            }
+            RuleParseError::InvalidLetter(count, letter) => {
+                write!(
+                    f,
+                    "`{}` isn't an arrangement of {} neighbours",
+                    letter, count
+                )
+            }
+            RuleParseError::InvalidStates(states) => {
+                write!(
+                    f,
+                    "`{}` is not a number of states between 2 and 255",
+                    states
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuleParseError {}
+
+/// Parses neighbour counts up to `max`.
+fn parse_counts(counts: &str, max: usize) -> Result<u16, RuleParseError> {
+    counts.chars().try_fold(0, |acc, c| match c.to_digit(10) {
+        Some(n) if n as usize <= max => Ok(acc | 1 << n),
+        _ => Err(RuleParseError::InvalidNeighbourCount(c)),
+    })
+}
+
+impl FromStr for GameRules {
+    type Err = RuleParseError;
+
+    /// Parses a Life-like rulestring, either `B3/S23` (in either order) or the older
+    /// `23/3`, followed by a number of states for Generations rules, `B2/S/C3` or `/2/3`.
+    /// Letters after the counts make an isotropic rule, like `B2-a/S12`. A `V`, `H`
+    /// or `@` and a mask at the end choose another neighbourhood. Larger than Life
+    /// rules are written like `R5,C0,M1,S34..58,B34..45,NM`, and named automata
+    /// like `WireWorld` by their name. One dimensional rules are written `W30` or
+    /// `C1635,K3,R1`, block rules `MS,D` and a table or a name like `Critters`, and
+    /// continuous rules by name and parameters like `Lenia,R13,T10,M0.15,S0.015,B1`.
+    /// Any of them can be followed by chances like `~B0.9,S0.95,N0.001`, and then
+    /// a bounded grid like `:T100,80`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(RuleParseError::Empty);
+        }
+        if let (s, Some(grid)) = BoundedGrid::split_suffix(s)? {
+            return match s.parse()? {
+                // Only one grid can be given.
+                GameRules::Bounded { grid, .. } => {
+                    let grid = grid.to_string();
+                    Err(RuleParseError::InvalidGrid(grid[1..].to_string()))
+                }
+                GameRules::Continuous(_) if grid.width == 0 || grid.height == 0 => Err(
+                    RuleParseError::Unsupported("continuous rules on grids that go on forever"),
+                ),
+                // Blocks have to tile the grid, whichever cells they start at.
+                rules
+                    if matches!(rules.base(), GameRules::Margolus(_))
+                        && (grid.width % 2 != 0 || grid.height % 2 != 0) =>
+                {
+                    let grid = grid.to_string();
+                    Err(RuleParseError::InvalidGrid(grid[1..].to_string()))
+                }
+                // Continuous rules keep every cell of the grid.
+                GameRules::Continuous(_)
+                    if grid.width as u64 * grid.height as u64 > continuous::MAX_AREA =>
+                {
+                    let grid = grid.to_string();
+                    Err(RuleParseError::InvalidGrid(grid[1..].to_string()))
+                }
+                // Rows are added below the last one, so the grid can only be a row.
+                GameRules::Wolfram(_) if grid.height != 0 => Err(RuleParseError::Unsupported(
+                    "one dimensional rules on grids with a height",
+                )),
+                GameRules::Wolfram(rule)
+                    if rule.fills_empty_rows() && grid.topology == Topology::Plane =>
+                {
+                    Err(RuleParseError::Unsupported(
+                        "one dimensional rules that fill empty rows on bounded planes",
+                    ))
+                }
+                rules if rules.has_b0() && grid.topology == Topology::Plane => {
+                    Err(RuleParseError::Unsupported("`B0` rules on bounded planes"))
+                }
+                rules => Ok(GameRules::Bounded {
+                    rules: Box::new(rules),
+                    grid,
+                }),
+            };
+        }
+        if let (s, Some(chances)) = Chances::split_suffix(s)? {
+            return match s.parse()? {
+                // Only one set of chances can be given.
+                GameRules::Stochastic { .. } => Err(RuleParseError::DuplicateSection('~')),
+                GameRules::Continuous(_) => {
+                    Err(RuleParseError::Unsupported("stochastic continuous rules"))
+                }
+                // Cells these rules move or repaint would be undone like failed births.
+                GameRules::Named(_)
+                | GameRules::Table(_)
+                | GameRules::Wolfram(_)
+                | GameRules::Margolus(_) => Err(RuleParseError::Unsupported(
+                    "stochastic rules without births and survivals",
+                )),
+                // Chances of birth and survival mean nothing to inverted cells.
+                rules if rules.has_b0() => {
+                    Err(RuleParseError::Unsupported("stochastic `B0` rules"))
+                }
+                rules => Ok(GameRules::Stochastic {
+                    rules: Box::new(rules),
+                    chances,
+                }),
+            };
+        }
+        if let Ok(automaton) = s.parse() {
+            return Ok(GameRules::Named(automaton));
+        }
+        if BlockRule::matches(s) {
+            return s.parse().map(GameRules::Margolus);
+        }
+        if ContinuousRule::matches(s) {
+            return s.parse().map(GameRules::Continuous);
+        }
+        if WolframRule::matches(s) {
+            return s.parse().map(GameRules::Wolfram);
+        }
+        if LargerThanLife::matches(s) {
+            return s.parse().map(GameRules::LargerThanLife);
+        }
+        let (s, neighbourhood) = Neighbourhood::split_suffix(s)?;
+        let parts = s.split('/').collect::<Vec<_>>();
+        if !(2..=3).contains(&parts.len()) {
+            return Err(RuleParseError::Malformed);
+        }
+
+        let section = |part: &str| match part.chars().next() {
+            Some('B' | 'b') => Some('B'),
+            Some('S' | 's') => Some('S'),
+            Some('C' | 'c' | 'G' | 'g') => Some('C'),
+            _ => None,
+        };
+
+        let (mut birth, mut survive, mut states) = (None, None, None);
+        if parts.iter().all(|part| section(part).is_some()) {
+            for part in &parts {
+                let letter = section(part).unwrap();
+                let slot = match letter {
+                    'B' => &mut birth,
+                    'S' => &mut survive,
+                    _ => &mut states,
+                };
+                if slot.replace(&part[1..]).is_some() {
+                    return Err(RuleParseError::DuplicateSection(letter));
+                }
+            }
+        } else if parts.iter().all(|part| section(part).is_none()) {
+            // No letters means the older S/B/C order.
+            survive = Some(parts[0]);
+            birth = Some(parts[1]);
+            states = parts.get(2).copied();
+        } else {
+            return Err(RuleParseError::Malformed);
+        }
+
+        let (birth, survive) = match (birth, survive) {
+            (Some(birth), Some(survive)) => (birth, survive),
+            _ => return Err(RuleParseError::Malformed),
+        };
+        let has_letters =
+            |counts: &str| counts.contains(|c: char| c.is_ascii_lowercase() || c == '-');
+        if has_letters(birth) || has_letters(survive) {
+            if states.is_some() || neighbourhood != Neighbourhood::Moore {
+                return Err(RuleParseError::Malformed);
+            }
+            let birth = hensel::parse(birth)?;
+            let survive = hensel::parse(survive)?;
+            // Letters that name every arrangement of their count make a Life-like rule.
+            return Ok(
+                match (hensel::totalistic(&birth), hensel::totalistic(&survive)) {
+                    (Some(birth), Some(survive)) => GameRules::LifeLike {
+                        birth,
+                        survive,
+                        neighbourhood,
+                    },
+                    _ => GameRules::Isotropic { birth, survive },
+                },
+            );
+        }
+        // Counts are single digits, so bigger neighbourhoods can't have them all.
+        let max = neighbourhood.offsets().len().min(9);
+        let (birth, survive) = (parse_counts(birth, max)?, parse_counts(survive, max)?);
+        match states.map(|states| (states, states.parse::<u8>())) {
+            None | Some((_, Ok(2))) => {
+                let rules = GameRules::LifeLike {
+                    birth,
+                    survive,
+                    neighbourhood,
+                };
+                if rules.has_b0() && !rules.runs_inverted() {
+                    Err(RuleParseError::Unsupported(
+                        "`B0` rules with more than 15 neighbours",
+                    ))
+                } else {
+                    Ok(rules)
+                }
+            }
+            Some((states, parsed)) => match parsed {
+                Ok(n) if n > 2 && birth & 1 != 0 => {
+                    Err(RuleParseError::Unsupported("Generations rules with `B0`"))
+                }
+                Ok(n) if n > 2 => Ok(GameRules::Generations {
+                    birth,
+                    survive,
+                    states: n,
+                    neighbourhood,
+                }),
+                _ => Err(RuleParseError::InvalidStates(states.to_string())),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The neighbour bits of a cell whose alive neighbours are at `alive`.
+    fn moore_neighbours(alive: &[(i32, i32)]) -> u32 {
+        let offsets = Neighbourhood::Moore.offsets();
+        alive
+            .iter()
+            .map(|pos| 1 << offsets.iter().position(|offset| offset == pos).unwrap())
+            .sum()
+    }
+
+    #[test]
+    fn rulestrings_round_trip() {
+        for rule in [
+            "B3/S23",
+            "B36/S23",
+            "B0123478/S34678",
+            "B/S012345678",
+            "B2/S/C3",
+            "B2/S345/C4",
+            "B2-a/S12",
+            "B3-cnqy/S23-a4itz",
+            "B2/S34H",
+            "B1/S12V",
+            "B3/S23@1ffbbff",
+            "R5,C0,M1,S34..58,B34..45,NM",
+            "R10,C3,M1,S1..2,B3..3,NN",
+            "WireWorld",
+            "Langtons-Ant",
+            "B3/S23:T100,80",
+            "B2/S/C3:K30*,20",
+            "WireWorld:P64,64",
+            "W30",
+            "C1635,K3,R1",
+            "Critters:T64,64",
+            "MS,D0;8;4;3;2;5;9;7;1;6;10;11;12;13;15;14",
+            "B3/S23~B0.8,S0.9,N0.002",
+            "B2/S34/C4~S0.7:T40,30",
+            "Lenia",
+            "Lenia,R20,T5,M0.2,S0.02,B1/0.5:P100,80",
+            "SmoothLife,R10,B0.25..0.35,D0.25..0.45,N0.03,M0.15,T4:K64*,64",
+        ] {
+            let rules = rule.parse::<GameRules>().unwrap();
+            let written = rules.to_string();
+            assert_eq!(
+                written.parse(),
+                Ok(rules),
+                "{} was written as {}",
+                rule,
+                written
+            );
+        }
+    }
+
+    #[test]
+    fn rulestring_spellings() {
+        let life = "B3/S23".parse::<GameRules>().unwrap();
+        assert_eq!(life, GameRules::conway());
+        for rule in ["23/3", "S23/B3", "b3/s23", " B3/S23 "] {
+            assert_eq!(rule.parse::<GameRules>().unwrap(), life, "{}", rule);
+        }
+        assert_eq!("/2/3".parse::<GameRules>().unwrap().to_string(), "B2/S/C3");
+        assert_eq!("B3/S23/C2".parse::<GameRules>().unwrap(), life);
+
+        assert_eq!("".parse::<GameRules>(), Err(RuleParseError::Empty));
+        assert_eq!(
+            "B3/B3".parse::<GameRules>(),
+            Err(RuleParseError::DuplicateSection('B'))
+        );
+        assert_eq!(
+            "B39/S23".parse::<GameRules>(),
+            Err(RuleParseError::InvalidNeighbourCount('9'))
+        );
+    }
+
+    #[test]
+    fn hensel_letters() {
+        let rule = "B2a/S".parse::<GameRules>().unwrap();
+        assert!(matches!(rule, GameRules::Isotropic { .. }));
+        // Two neighbours next to each other, whichever way round they are.
+        for adjacent in [[(0, 1), (1, 1)], [(1, 0), (1, -1)], [(-1, -1), (0, -1)]] {
+            assert_eq!(rule.next_state(0, moore_neighbours(&adjacent)), 1);
+        }
+        for apart in [[(0, 1), (0, -1)], [(1, 1), (-1, -1)], [(-1, 1), (1, 1)]] {
+            assert_eq!(rule.next_state(0, moore_neighbours(&apart)), 0);
+        }
+
+        // `-` takes letters away from every arrangement of the count.
+        let rule = "B2-a/S".parse::<GameRules>().unwrap();
+        assert_eq!(rule.next_state(0, moore_neighbours(&[(0, 1), (1, 1)])), 0);
+        assert_eq!(rule.next_state(0, moore_neighbours(&[(0, 1), (0, -1)])), 1);
+
+        // Every letter of a count is the same as the count on its own.
+        assert_eq!(
+            "B2cekain/S12345678".parse::<GameRules>(),
+            "B2/S12345678".parse()
+        );
+        assert_eq!(
+            "B2z/S".parse::<GameRules>(),
+            Err(RuleParseError::InvalidLetter(2, 'z'))
+        );
+    }
+}
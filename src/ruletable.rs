@@ -0,0 +1,768 @@
+//! Golly's [rule table](https://golly.sourceforge.io/Help/formats.html#rule) files,
+//! `.rule` files with a `@TABLE` of transitions and optionally `@COLORS` for their states.
+
+use std::fmt;
+
+use bevy::math::IVec2;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::neighbourhood::Neighbourhood;
+
+/// The most transitions a table can expand to once its variables and
+/// symmetries are written out.
+const MAX_TRANSITIONS: usize = 1 << 20;
+
+/// The most neighbourhoods a table is looked up in as an array, one with more
+/// possible neighbourhoods keeps only the ones its transitions match.
+const MAX_DENSE_LOOKUP: usize = 1 << 24;
+
+/// The colours of the first and last alive states when `@COLORS` doesn't give
+/// them, like in Golly.
+const DEFAULT_GRADIENT: ([u8; 3], [u8; 3]) = ([255, 0, 0], [255, 255, 0]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleTable {
+    /// The name from the `@RULE` line.
+    pub name: String,
+    pub states: u8,
+    /// The neighbours in the order the table lists them, with y up.
+    pub neighbourhood: Neighbourhood,
+    /// The colour of each state as RGB, if the file has a `@COLORS` section.
+    pub palette: Option<Vec<[u8; 3]>>,
+    /// The state each neighbourhood leads to.
+    lookup: Lookup,
+}
+
+/// The state every neighbourhood a table matches leads to, the cell itself first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Lookup {
+    /// Every neighbourhood, indexed by its states as the digits of a number in
+    /// base `states` from the cell itself.
+    Dense(Vec<u8>),
+    /// Only the neighbourhoods a transition matches.
+    Sparse(HashMap<Vec<u8>, u8>),
+}
+
+#[derive(Debug)]
+pub enum RuleTableError {
+    /// There's no `@RULE` line naming the rule.
+    MissingName,
+    /// There's no `@TABLE` section.
+    MissingTable,
+    /// The rule is given as a `@TREE`, which isn't supported.
+    Tree,
+    BadLine {
+        line: usize,
+        expected: String,
+    },
+    /// The variables and symmetries make more transitions than can be looked up quickly.
+    TooLarge,
+    /// Empty cells with only empty neighbours change, which would change every
+    /// cell away from the pattern.
+    EmptyChanges,
+}
+
+impl fmt::Display for RuleTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleTableError::MissingName => write!(f, "missing `@RULE` line"),
+            RuleTableError::MissingTable => write!(f, "missing `@TABLE` section"),
+            RuleTableError::Tree => write!(f, "`@TREE` rules aren't supported"),
+            RuleTableError::BadLine { line, expected } => {
+                write!(f, "line {}: expected {}", line, expected)
+            }
+            RuleTableError::TooLarge => {
+                write!(f, "the table has more than {} transitions", MAX_TRANSITIONS)
+            }
+            RuleTableError::EmptyChanges => {
+                write!(
+                    f,
+                    "empty cells with only empty neighbours have to stay empty"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuleTableError {}
+
+/// A state or a variable in a transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Token {
+    State(u8),
+    /// A variable used more than once, which has the same value everywhere.
+    Variable(usize),
+    /// A variable used once, which can be any of its values.
+    Any(usize),
+}
+
+/// A table's neighbourhood, which says where its neighbours are and how they
+/// can be rearranged by symmetries.
+struct TableNeighbourhood {
+    /// The neighbours in the order of the table, with y up.
+    offsets: &'static [(i32, i32)],
+    /// Reflections map neighbour `i` to `(reflect_axis - i) mod len`, treating the
+    /// neighbours as a ring.
+    reflect_axis: usize,
+}
+
+impl TableNeighbourhood {
+    fn from_name(name: &str) -> Option<Self> {
+        let (offsets, reflect_axis): (&'static [(i32, i32)], _) = match name {
+            "vonNeumann" => (&[(0, 1), (1, 0), (0, -1), (-1, 0)], 0),
+            "Moore" => (
+                &[
+                    (0, 1),
+                    (1, 1),
+                    (1, 0),
+                    (1, -1),
+                    (0, -1),
+                    (-1, -1),
+                    (-1, 0),
+                    (-1, 1),
+                ],
+                0,
+            ),
+            "hexagonal" => (&[(0, 1), (1, 0), (1, -1), (0, -1), (-1, 0), (-1, 1)], 1),
+            "oneDimensional" => (&[(-1, 0), (1, 0)], 1),
+            _ => return None,
+        };
+        Some(Self {
+            offsets,
+            reflect_axis,
+        })
+    }
+
+    /// The rearrangements of the neighbours a symmetry allows, where neighbour
+    /// `i` of a transition moves to `permutation[i]`. `None` means every one.
+    fn symmetries(&self, name: &str) -> Option<Option<Vec<Vec<usize>>>> {
+        let len = self.offsets.len();
+        if name == "permute" {
+            return Some(None);
+        }
+        let (rotations, reflect) = match name {
+            "none" => (1, false),
+            "reflect" | "reflect_horizontal" => (1, true),
+            _ => {
+                let name = name.strip_prefix("rotate")?;
+                let (rotations, reflect) = match name.strip_suffix("reflect") {
+                    Some(rotations) => (rotations, true),
+                    None => (name, false),
+                };
+                (rotations.parse::<usize>().ok()?, reflect)
+            }
+        };
+        if rotations == 0 || !len.is_multiple_of(rotations) {
+            return None;
+        }
+        let step = len / rotations;
+        let mut permutations = Vec::new();
+        for rotation in 0..rotations {
+            let shift = rotation * step;
+            permutations.push((0..len).map(|i| (i + shift) % len).collect());
+            if reflect {
+                let axis = self.reflect_axis + len;
+                permutations.push((0..len).map(|i| (axis - (i + shift) % len) % len).collect());
+            }
+        }
+        Some(Some(permutations))
+    }
+}
+
+/// Rearranges `tokens` into the next permutation in lexicographic order, returning
+/// `false` once they're back to the first.
+fn next_permutation(tokens: &mut [Token]) -> bool {
+    let i = match tokens.windows(2).rposition(|w| w[0] < w[1]) {
+        Some(i) => i,
+        None => {
+            tokens.reverse();
+            return false;
+        }
+    };
+    let j = tokens.iter().rposition(|&t| t > tokens[i]).unwrap();
+    tokens.swap(i, j);
+    tokens[i + 1..].reverse();
+    true
+}
+
+impl RuleTable {
+    /// Whether `rule` could be the name of a `.rule` file rather than a rulestring,
+    /// a letter followed by letters, digits, `-` and `_`.
+    pub fn is_name(rule: &str) -> bool {
+        rule.starts_with(|c: char| c.is_ascii_alphabetic())
+            && rule
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_".contains(c))
+    }
+
+    /// Parses a `.rule` file. Sections other than `@RULE`, `@TABLE` and `@COLORS`
+    /// are ignored.
+    pub fn parse(input: &str) -> Result<RuleTable, RuleTableError> {
+        let mut name = None;
+        let mut section = "";
+        let mut table = Vec::new();
+        let mut colors = Vec::new();
+        for (number, line) in input.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(header) = line.strip_prefix('@') {
+                let mut words = header.split_whitespace();
+                section = words.next().unwrap_or("");
+                match section {
+                    "RULE" => name = words.next().map(str::to_string),
+                    "TREE" => return Err(RuleTableError::Tree),
+                    _ => {}
+                }
+                continue;
+            }
+            match section {
+                "TABLE" => table.push((number + 1, line)),
+                "COLORS" => colors.push((number + 1, line)),
+                _ => {}
+            }
+        }
+        let name = name.ok_or(RuleTableError::MissingName)?;
+        if table.is_empty() {
+            return Err(RuleTableError::MissingTable);
+        }
+
+        let mut rule = RuleTable::compile(name, &table)?;
+        // Only cells near ones that aren't empty are stepped.
+        let empty = vec![0; rule.neighbourhood.offsets().len() + 1];
+        if rule.next_state(&empty) != 0 {
+            return Err(RuleTableError::EmptyChanges);
+        }
+        if !colors.is_empty() {
+            rule.palette = Some(palette(rule.states, &colors)?);
+        }
+        Ok(rule)
+    }
+
+    /// Expands the variables and symmetries of the lines of a `@TABLE` into
+    /// transitions and builds the sets used to look them up.
+    fn compile(name: String, lines: &[(usize, &str)]) -> Result<RuleTable, RuleTableError> {
+        let mut states = None;
+        let mut neighbourhood = None;
+        let mut symmetries = Some(Vec::new());
+        let mut variables: Vec<(&str, Vec<u8>)> = Vec::new();
+        // Each transition as the cell, its neighbours and the state it moves to.
+        let mut transitions: Vec<(Vec<Vec<u8>>, u8)> = Vec::new();
+
+        for &(number, line) in lines {
+            let bad_line = |expected: &str| RuleTableError::BadLine {
+                line: number,
+                expected: expected.to_string(),
+            };
+            if let Some((key, value)) = line.split_once(':') {
+                let value = value.trim();
+                match key.trim() {
+                    "n_states" => match value.parse::<u8>() {
+                        Ok(n) if n >= 2 => states = Some(n),
+                        _ => return Err(bad_line("a number of states between 2 and 255")),
+                    },
+                    "neighborhood" => {
+                        let table_neighbourhood =
+                            TableNeighbourhood::from_name(value).ok_or_else(|| {
+                                bad_line("`vonNeumann`, `Moore`, `hexagonal` or `oneDimensional`")
+                            })?;
+                        symmetries = Some(vec![(0..table_neighbourhood.offsets.len()).collect()]);
+                        neighbourhood = Some(table_neighbourhood);
+                    }
+                    "symmetries" => {
+                        symmetries = neighbourhood
+                            .as_ref()
+                            .ok_or_else(|| bad_line("`neighborhood` before `symmetries`"))?
+                            .symmetries(value)
+                            .ok_or_else(|| bad_line("symmetries the neighbourhood has"))?;
+                    }
+                    _ => return Err(bad_line("`n_states`, `neighborhood` or `symmetries`")),
+                }
+                continue;
+            }
+
+            let (states, neighbourhood) = match (states, &neighbourhood) {
+                (Some(states), Some(neighbourhood)) => (states, neighbourhood),
+                _ => {
+                    return Err(bad_line(
+                        "`n_states` and `neighborhood` before any transitions",
+                    ))
+                }
+            };
+            let token = |word: &str| match word.parse::<u8>() {
+                Ok(state) if state < states => Some(Token::State(state)),
+                Ok(_) => None,
+                Err(_) => variables
+                    .iter()
+                    .rposition(|(name, _)| *name == word)
+                    .map(Token::Variable),
+            };
+
+            if let Some(definition) = line.strip_prefix("var ") {
+                let (variable, values) = definition
+                    .split_once('=')
+                    .ok_or_else(|| bad_line("`var name={...}`"))?;
+                let values = values
+                    .trim()
+                    .strip_prefix('{')
+                    .and_then(|values| values.strip_suffix('}'))
+                    .ok_or_else(|| bad_line("values between `{` and `}`"))?;
+                let mut set = Vec::new();
+                for word in values.split(',').map(str::trim) {
+                    match token(word) {
+                        Some(Token::State(state)) => set.push(state),
+                        Some(Token::Variable(i) | Token::Any(i)) => set.extend(&variables[i].1),
+                        None => return Err(bad_line("states or variables defined earlier")),
+                    }
+                }
+                set.sort_unstable();
+                set.dedup();
+                variables.push((variable.trim(), set));
+                continue;
+            }
+
+            // Tables of up to ten states can leave out the commas, between all
+            // of the states or just some of them.
+            let words = if line.contains(',') {
+                line.split(',')
+                    .map(str::trim)
+                    .flat_map(|word| {
+                        if states <= 10 && word.bytes().all(|b| b.is_ascii_digit()) {
+                            word.split("").filter(|w| !w.is_empty()).collect()
+                        } else {
+                            vec![word]
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            } else {
+                line.split("").filter(|w| !w.trim().is_empty()).collect()
+            };
+            let len = neighbourhood.offsets.len() + 2;
+            if words.len() != len {
+                return Err(bad_line(&format!("a transition of {} states", len)));
+            }
+            let tokens = words
+                .iter()
+                .map(|&word| token(word))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| bad_line("states or variables"))?;
+            if let Token::Variable(output) = tokens[len - 1] {
+                if !tokens[..len - 1].contains(&Token::Variable(output)) {
+                    return Err(bad_line(
+                        "an output that's a state or a variable used before it",
+                    ));
+                }
+            }
+
+            // Variables used once with the same values are interchangeable, so
+            // symmetries don't make copies of a transition that only swap them.
+            let tokens = tokens
+                .iter()
+                .map(|&token| match token {
+                    Token::Variable(i) if tokens.iter().filter(|&&t| t == token).count() == 1 => {
+                        let values = &variables[i].1;
+                        Token::Any(variables.iter().position(|(_, v)| v == values).unwrap())
+                    }
+                    token => token,
+                })
+                .collect::<Vec<_>>();
+            for tokens in arrangements(&tokens, symmetries.as_deref()) {
+                expand(&tokens, &variables, &mut transitions);
+                if transitions.len() > MAX_TRANSITIONS {
+                    return Err(RuleTableError::TooLarge);
+                }
+            }
+        }
+
+        let states = states.ok_or_else(|| RuleTableError::BadLine {
+            line: lines[0].0,
+            expected: "`n_states`".to_string(),
+        })?;
+        let offsets = neighbourhood
+            .ok_or_else(|| RuleTableError::BadLine {
+                line: lines[0].0,
+                expected: "`neighborhood`".to_string(),
+            })?
+            .offsets;
+
+        // The first transition that matches is used, so they're written last to
+        // first over the ones after them.
+        let size = (states as usize)
+            .checked_pow(offsets.len() as u32 + 1)
+            .filter(|&size| size <= MAX_DENSE_LOOKUP);
+        let lookup = match size {
+            Some(size) => {
+                // Cells that no transition matches stay as they are.
+                let per_state = size / states as usize;
+                let mut next = (0..size).map(|i| (i / per_state) as u8).collect::<Vec<_>>();
+                for (inputs, output) in transitions.iter().rev() {
+                    neighbourhoods(inputs, |cells| next[index(cells, states)] = *output);
+                }
+                Lookup::Dense(next)
+            }
+            None => {
+                let mut next = HashMap::default();
+                let mut count = 0usize;
+                for (inputs, output) in transitions.iter().rev() {
+                    let matches = inputs
+                        .iter()
+                        .map(Vec::len)
+                        .try_fold(1usize, usize::checked_mul);
+                    count = count.saturating_add(matches.unwrap_or(usize::MAX));
+                    if count > MAX_TRANSITIONS {
+                        return Err(RuleTableError::TooLarge);
+                    }
+                    neighbourhoods(inputs, |cells| {
+                        next.insert(cells.to_vec(), *output);
+                    });
+                }
+                Lookup::Sparse(next)
+            }
+        };
+        Ok(RuleTable {
+            name,
+            states,
+            neighbourhood: Neighbourhood::Custom(offsets.to_vec()),
+            palette: None,
+            lookup,
+        })
+    }
+
+    /// The state a cell moves to, given its state followed by those of its
+    /// neighbours in the order of `neighbourhood`. The first transition that
+    /// matches is used, and cells that none match stay as they are.
+    pub fn next_state(&self, cells: &[u8]) -> u8 {
+        if cells.iter().any(|&state| state >= self.states) {
+            return cells[0];
+        }
+        match &self.lookup {
+            Lookup::Dense(next) => next[index(cells, self.states)],
+            Lookup::Sparse(next) => next.get(cells).copied().unwrap_or(cells[0]),
+        }
+    }
+}
+
+/// The index of a neighbourhood in a dense lookup.
+fn index(cells: &[u8], states: u8) -> usize {
+    cells
+        .iter()
+        .fold(0, |index, &state| index * states as usize + state as usize)
+}
+
+/// Calls `f` with every neighbourhood that has each cell in one of its `inputs`.
+fn neighbourhoods(inputs: &[Vec<u8>], mut f: impl FnMut(&[u8])) {
+    let mut choices = vec![0; inputs.len()];
+    let mut cells = inputs.iter().map(|input| input[0]).collect::<Vec<_>>();
+    loop {
+        f(&cells);
+        let next = choices
+            .iter()
+            .zip(inputs)
+            .position(|(&choice, input)| choice + 1 < input.len());
+        match next {
+            Some(i) => {
+                choices[i] += 1;
+                cells[i] = inputs[i][choices[i]];
+                for j in 0..i {
+                    choices[j] = 0;
+                    cells[j] = inputs[j][0];
+                }
+            }
+            None => return,
+        }
+    }
+}
+
+/// Every distinct rearrangement of the neighbours of `tokens` allowed by
+/// `symmetries`, or every one if it's `None`.
+fn arrangements(tokens: &[Token], symmetries: Option<&[Vec<usize>]>) -> Vec<Vec<Token>> {
+    let neighbours = 1..tokens.len() - 1;
+    let mut seen = HashSet::default();
+    let mut arranged = Vec::new();
+    let mut add = |neighbour_tokens: &[Token]| {
+        let mut tokens = tokens.to_vec();
+        tokens[neighbours.clone()].copy_from_slice(neighbour_tokens);
+        if seen.insert(tokens.clone()) {
+            arranged.push(tokens);
+        }
+    };
+    match symmetries {
+        Some(permutations) => {
+            for permutation in permutations {
+                let mut moved = tokens[neighbours.clone()].to_vec();
+                for (i, &to) in permutation.iter().enumerate() {
+                    moved[to] = tokens[1 + i];
+                }
+                add(&moved);
+            }
+        }
+        None => {
+            let mut moved = tokens[neighbours.clone()].to_vec();
+            moved.sort_unstable();
+            add(&moved);
+            while next_permutation(&mut moved) {
+                add(&moved);
+            }
+        }
+    }
+    arranged
+}
+
+/// Adds the transitions `tokens` stands for to `transitions`, one for each
+/// combination of values of its bound variables.
+fn expand(
+    tokens: &[Token],
+    variables: &[(&str, Vec<u8>)],
+    transitions: &mut Vec<(Vec<Vec<u8>>, u8)>,
+) {
+    let mut bound = tokens
+        .iter()
+        .filter_map(|token| match token {
+            Token::Variable(i) => Some(*i),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    bound.sort_unstable();
+    bound.dedup();
+
+    // Counts through every combination of values of the bound variables.
+    let mut choices = vec![0; bound.len()];
+    loop {
+        let value = |token: &Token| match *token {
+            Token::State(state) => vec![state],
+            Token::Variable(i) => {
+                let b = bound.iter().position(|&b| b == i).unwrap();
+                vec![variables[i].1[choices[b]]]
+            }
+            Token::Any(i) => variables[i].1.clone(),
+        };
+        let (output, inputs) = tokens.split_last().unwrap();
+        transitions.push((inputs.iter().map(value).collect(), value(output)[0]));
+
+        let next = choices
+            .iter()
+            .zip(&bound)
+            .position(|(&choice, &i)| choice + 1 < variables[i].1.len());
+        match next {
+            Some(b) => {
+                choices[b] += 1;
+                choices[..b].iter_mut().for_each(|choice| *choice = 0);
+            }
+            None => return,
+        }
+    }
+}
+
+/// Reads the lines of a `@COLORS` section, each either a state and its colour or
+/// two colours for a gradient across the alive states.
+fn palette(states: u8, lines: &[(usize, &str)]) -> Result<Vec<[u8; 3]>, RuleTableError> {
+    let gradient = |(from, to): ([u8; 3], [u8; 3]), state: u8| {
+        let t = match states {
+            2 => 0.,
+            _ => (state - 1) as f32 / (states - 2) as f32,
+        };
+        [0, 1, 2].map(|i| (from[i] as f32 + (to[i] as f32 - from[i] as f32) * t).round() as u8)
+    };
+    let mut colors = (0..states)
+        .map(|state| match state {
+            0 => [0, 0, 0],
+            _ => gradient(DEFAULT_GRADIENT, state),
+        })
+        .collect::<Vec<_>>();
+
+    for &(number, line) in lines {
+        let numbers = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .map(str::parse::<u8>)
+            .collect::<Result<Vec<_>, _>>();
+        match numbers.as_deref() {
+            Ok(&[state, r, g, b]) if state < states => colors[state as usize] = [r, g, b],
+            Ok(&[r1, g1, b1, r2, g2, b2]) => {
+                for state in 1..states {
+                    colors[state as usize] = gradient(([r1, g1, b1], [r2, g2, b2]), state);
+                }
+            }
+            _ => {
+                return Err(RuleTableError::BadLine {
+                    line: number,
+                    expected: "`state r g b` or `r g b r g b`".to_string(),
+                })
+            }
+        }
+    }
+    Ok(colors)
+}
+
+/// Computes the next state of every cell that isn't dead and of their neighbours.
+pub(crate) fn step(cells: &HashMap<IVec2, u8>, table: &RuleTable) -> HashMap<IVec2, u8> {
+    let offsets = table.neighbourhood.offsets();
+    let candidates = cells
+        .keys()
+        .flat_map(|&pos| {
+            std::iter::once(pos).chain(offsets.iter().map(move |&o| pos - IVec2::from(o)))
+        })
+        .collect::<HashSet<_>>();
+
+    let state = |pos: IVec2| cells.get(&pos).copied().unwrap_or(0);
+    let mut neighbourhood = Vec::with_capacity(offsets.len() + 1);
+    candidates
+        .into_iter()
+        .filter_map(|pos| {
+            neighbourhood.clear();
+            neighbourhood.push(state(pos));
+            neighbourhood.extend(offsets.iter().map(|&o| state(pos + IVec2::from(o))));
+            let next = table.next_state(&neighbourhood);
+            (next != 0).then_some((pos, next))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::rules::GameRules;
+    use crate::stochastic::Randomness;
+    use crate::universe::Universe;
+
+    const LIFE: &str = "@RULE LifeTable
+@TABLE
+n_states:2
+neighborhood:Moore
+symmetries:permute
+var a={0,1}
+var b={a}
+var c={a}
+var d={a}
+var e={a}
+var f={a}
+var g={a}
+var h={a}
+0,1,1,1,0,0,0,0,0,1
+1,1,1,0,0,0,0,0,0,1
+1,1,1,1,0,0,0,0,0,1
+1,a,b,c,d,e,f,g,h,0
+";
+
+    fn sorted(universe: &Universe) -> Vec<(i32, i32, u8)> {
+        let mut cells = universe
+            .iter_states()
+            .map(|(pos, state)| (pos.x, pos.y, state))
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn life_as_a_table() {
+        let table = GameRules::Table(Arc::new(RuleTable::parse(LIFE).unwrap()));
+        let mut universes = [table, GameRules::conway()].map(|rules| {
+            let mut universe = Universe::new(rules.clone());
+            universe.set_rules(rules);
+            universe
+        });
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..300 {
+            let pos = IVec2::new(rng.gen_range(-15..15), rng.gen_range(-15..15));
+            for universe in &mut universes {
+                universe.set(pos, true);
+            }
+        }
+        let mut randomness = Randomness::new(0);
+        for generation in 1..=20 {
+            for universe in &mut universes {
+                universe.step(&mut randomness);
+            }
+            assert_eq!(
+                sorted(&universes[0]),
+                sorted(&universes[1]),
+                "{}",
+                generation
+            );
+        }
+    }
+
+    #[test]
+    fn transitions() {
+        let table = RuleTable::parse(
+            "@RULE Test
+@TABLE
+n_states:3
+neighborhood:vonNeumann
+symmetries:rotate4
+var a={1,2}
+0,1,0,0,0,2
+0,a,0,0,0,1
+0,a,0,0,a,1
+1,a,a,a,a,0
+@COLORS
+1 255 0 0
+",
+        )
+        .unwrap();
+        // The first transition that matches is used, in any rotation.
+        assert_eq!(table.next_state(&[0, 0, 0, 1, 0]), 2);
+        assert_eq!(table.next_state(&[0, 0, 0, 2, 0]), 1);
+        assert_eq!(table.next_state(&[0, 2, 0, 0, 2]), 1);
+        assert_eq!(table.next_state(&[0, 2, 0, 0, 1]), 0);
+        assert_eq!(table.next_state(&[1, 2, 2, 2, 2]), 0);
+        // Cells that nothing matches stay as they are.
+        assert_eq!(table.next_state(&[1, 2, 1, 2, 0]), 1);
+        assert_eq!(table.palette.as_ref().unwrap()[1], [255, 0, 0]);
+
+        // Too many states for an array, so only the neighbourhoods that match are kept.
+        let table = RuleTable::parse(
+            "@RULE Many
+@TABLE
+n_states:12
+neighborhood:Moore
+symmetries:none
+var a={0,11}
+0,5,0,0,0,0,0,0,a,7
+",
+        )
+        .unwrap();
+        assert!(matches!(table.lookup, Lookup::Sparse(_)));
+        assert_eq!(table.next_state(&[0, 5, 0, 0, 0, 0, 0, 0, 11]), 7);
+        assert_eq!(table.next_state(&[0, 5, 0, 0, 0, 0, 0, 11, 0]), 0);
+    }
+
+    #[test]
+    fn errors() {
+        let table = |lines: &str| RuleTable::parse(&format!("@RULE R\n@TABLE\n{}", lines));
+        assert!(matches!(
+            RuleTable::parse("@TABLE\nn_states:2\nneighborhood:Moore\n"),
+            Err(RuleTableError::MissingName)
+        ));
+        assert!(matches!(
+            RuleTable::parse("@RULE R\n@TREE\n"),
+            Err(RuleTableError::Tree)
+        ));
+        assert!(matches!(
+            table("n_states:2\nneighborhood:Moore\n0,1,0\n"),
+            Err(RuleTableError::BadLine { line: 5, .. })
+        ));
+        assert!(matches!(
+            table("n_states:2\nneighborhood:vonNeumann\n0,0,0,0,0,1\n"),
+            Err(RuleTableError::EmptyChanges)
+        ));
+        let states = (0..40).map(|s| s.to_string()).collect::<Vec<_>>().join(",");
+        let any = format!(
+            "var a={{{}}}\nvar b={{a}}\nvar c={{a}}\nvar d={{a}}\n",
+            states
+        );
+        assert!(matches!(
+            table(&format!(
+                "n_states:40\nneighborhood:vonNeumann\nsymmetries:none\n{}1,a,b,c,d,0\n",
+                any
+            )),
+            Err(RuleTableError::TooLarge)
+        ));
+    }
+}
@@ -0,0 +1,83 @@
+use bevy::prelude::*;
+
+use automata::{macrocell, universe::Universe};
+
+use crate::board_asset::{rle, write_cells};
+use crate::message::ShowMessage;
+use crate::selection::Selection;
+
+/// The name and comments of the pattern that was loaded, used to describe saved
+/// copies of it.
+#[derive(Debug, Default)]
+pub struct PatternInfo {
+    pub name: String,
+    pub comments: Vec<String>,
+}
+
+/// Where saved patterns are written, so they can be loaded again by name.
+const SAVE_DIRECTORY: &str = "assets";
+
+fn write_pattern(name: &str, contents: String) {
+    let path = std::path::Path::new(SAVE_DIRECTORY).join(name);
+    match std::fs::write(&path, contents) {
+        Ok(()) => println!("Saved: {}", path.display()),
+        Err(e) => eprintln!("Couldn't save {}: {}", path.display(), e),
+    }
+}
+
+/// `R` saves the universe as an RLE file, `M` as a Macrocell file and `C` the
+/// selection (or everything) as a plaintext file.
+fn save_pattern(
+    keyboard_input: Res<Input<KeyCode>>,
+    universe: Res<Universe>,
+    info: Res<PatternInfo>,
+    selection: Res<Selection>,
+    mut messages: EventWriter<ShowMessage>,
+) {
+    if !keyboard_input.any_just_pressed([KeyCode::R, KeyCode::C, KeyCode::M]) {
+        return;
+    }
+    let mut comments = info.comments.clone();
+    comments.push(format!(
+        "Saved from automata at generation {}.",
+        universe.generation()
+    ));
+    // Pattern files can't say every cell away from the pattern is alive, so like
+    // Golly the complement is saved with the rule that steps it instead.
+    let complement;
+    let universe = match (universe.background(), universe.complement()) {
+        (false, _) => &*universe,
+        (true, Some(universe)) => {
+            comments.push("The background was alive, so these are the dead cells.".to_string());
+            complement = universe;
+            &complement
+        }
+        (true, None) => {
+            let message = format!(
+                "Can't save while the background is alive, {} has no complement that can be written",
+                universe.rules()
+            );
+            eprintln!("{}", message);
+            messages.send(ShowMessage(message));
+            return;
+        }
+    };
+
+    if keyboard_input.just_pressed(KeyCode::R) {
+        write_pattern("saved.rle", rle::write(universe, &info.name, &comments));
+    }
+    if keyboard_input.just_pressed(KeyCode::C) {
+        let cells = write_cells(universe, selection.region(), &info.name, &comments);
+        write_pattern("saved.cells", cells);
+    }
+    if keyboard_input.just_pressed(KeyCode::M) {
+        write_pattern("saved.mc", macrocell::write(universe));
+    }
+}
+
+pub(crate) struct SavePlugin;
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(save_pattern);
+    }
+}
@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+
+use crate::{board::GamePlaying, hoverable::Hovering, render::TilePosition, view::View};
+
+/// A rectangle of the board picked out by dragging with the right mouse button,
+/// as its inclusive minimum and maximum corners.
+#[derive(Debug, Default)]
+pub struct Selection {
+    anchor: Option<IVec2>,
+    region: Option<(IVec2, IVec2)>,
+}
+
+impl Selection {
+    pub fn region(&self) -> Option<(IVec2, IVec2)> {
+        self.region
+    }
+
+    pub fn contains(&self, pos: IVec2) -> bool {
+        self.region
+            .is_some_and(|(min, max)| pos.cmpge(min).all() && pos.cmple(max).all())
+    }
+}
+
+fn select_region(
+    mouse_input: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    hovering: Query<&TilePosition, With<Hovering>>,
+    view: Query<&View>,
+    mut selection: ResMut<Selection>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        *selection = Selection::default();
+        return;
+    }
+
+    let offset = view.iter().next().unwrap().offset;
+    let hovered = match hovering.iter().next() {
+        Some(tile) => tile.as_ivec2() + offset,
+        None => return,
+    };
+    if mouse_input.just_pressed(MouseButton::Right) {
+        selection.anchor = Some(hovered);
+    }
+    if mouse_input.pressed(MouseButton::Right) {
+        if let Some(anchor) = selection.anchor {
+            selection.region = Some((anchor.min(hovered), anchor.max(hovered)));
+        }
+    }
+}
+
+pub(crate) struct SelectionPlugin;
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .add_system_set(SystemSet::on_update(GamePlaying::Paused).with_system(select_region));
+    }
+}
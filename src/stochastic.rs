@@ -0,0 +1,323 @@
+//! Rules that only happen some of the time: a suffix like `~B0.9,S0.95,N0.001`
+//! makes cells that would be born or survive only do so with those chances, and
+//! flips cells between alive and dead at random as noise.
+//!
+//! Every random choice comes from a seeded `Randomness`, so a run can be replayed
+//! exactly by starting it again from the same pattern and seed.
+
+use std::fmt;
+
+use bevy::math::IVec2;
+use bevy::utils::HashMap;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::bounded::BoundedGrid;
+use crate::rules::RuleParseError;
+
+/// The random numbers that stochastic rules are stepped with.
+#[derive(Debug, Clone)]
+pub struct Randomness {
+    seed: u64,
+    rng: StdRng,
+}
+
+impl Randomness {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Goes back to the start of the seed's numbers, to replay a run.
+    pub fn reseed(&mut self) {
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+
+    /// The key every random choice of a generation is made from.
+    pub(crate) fn next_key(&mut self) -> u64 {
+        self.rng.gen()
+    }
+}
+
+impl Default for Randomness {
+    /// A new random seed.
+    fn default() -> Self {
+        Randomness::new(rand::random())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Chances {
+    /// The chance a cell the rule gives birth to is actually born.
+    pub birth: f64,
+    /// The chance a cell the rule keeps alive actually survives.
+    pub survive: f64,
+    /// The chance each cell is flipped between alive and dead every generation.
+    pub noise: f64,
+}
+
+// The chances are always between 0 and 1, never NaN.
+impl Eq for Chances {}
+
+impl Default for Chances {
+    /// Everything happens the way the rule says it does.
+    fn default() -> Self {
+        Self {
+            birth: 1.,
+            survive: 1.,
+            noise: 0.,
+        }
+    }
+}
+
+impl Chances {
+    /// Splits a `~B0.9,S0.95,N0.001` style suffix off the end of a rulestring.
+    pub(crate) fn split_suffix(rule: &str) -> Result<(&str, Option<Chances>), RuleParseError> {
+        let (rule, suffix) = match rule.rsplit_once('~') {
+            Some(split) => split,
+            None => return Ok((rule, None)),
+        };
+        let mut chances = Chances::default();
+        for part in suffix.split(',').map(str::trim) {
+            let invalid = || RuleParseError::InvalidParameter(part.to_string());
+            let mut chars = part.chars();
+            let chance = match chars.next().map(|c| c.to_ascii_uppercase()) {
+                Some('B') => &mut chances.birth,
+                Some('S') => &mut chances.survive,
+                Some('N') => &mut chances.noise,
+                _ => return Err(invalid()),
+            };
+            *chance = match chars.as_str().parse() {
+                Ok(p) if (0. ..=1.).contains(&p) => p,
+                _ => return Err(invalid()),
+            };
+        }
+        Ok((rule, Some(chances)))
+    }
+
+    /// Undoes the births and survivals in `next`, the generation after `cells`,
+    /// that don't happen by chance. Cells that fail to survive start dying like
+    /// they do when the rule kills them, as state `2` with `states` above that.
+    pub(crate) fn apply(
+        &self,
+        cells: &HashMap<IVec2, u8>,
+        mut next: HashMap<IVec2, u8>,
+        states: u8,
+        key: u64,
+    ) -> HashMap<IVec2, u8> {
+        next.retain(|&pos, state| {
+            let previous = cells.get(&pos).copied().unwrap_or(0);
+            let chance = match (previous, *state) {
+                (0, _) => self.birth,
+                (1, 1) => self.survive,
+                _ => return true,
+            };
+            if uniform(key, pos) < chance {
+                return true;
+            }
+            *state = if previous == 1 && states > 2 { 2 } else { 0 };
+            *state != 0
+        });
+        next
+    }
+
+    /// Flips cells with the chance of noise. That's every cell of `grid`, or
+    /// within the bounding box of `cells` the ways the grid goes on forever.
+    pub(crate) fn add_noise(
+        &self,
+        cells: &mut HashMap<IVec2, u8>,
+        grid: Option<&BoundedGrid>,
+        key: u64,
+    ) {
+        if self.noise <= 0. {
+            return;
+        }
+        let (mut min, mut max) = match bounding_box(cells) {
+            Some(bounds) => bounds,
+            None if grid.is_none() => return,
+            None => (IVec2::ZERO, IVec2::ZERO),
+        };
+        if let Some(grid) = grid {
+            let (grid_min, grid_max) = grid.bounds();
+            if grid.width > 0 {
+                (min.x, max.x) = (grid_min.x, grid_max.x);
+            }
+            if grid.height > 0 {
+                (min.y, max.y) = (grid_min.y, grid_max.y);
+            }
+        }
+
+        // Rather than a choice per cell, skip straight to the next flipped one,
+        // the gaps between them are geometrically distributed.
+        let width = (max.x - min.x + 1) as u64;
+        let area = width * (max.y - min.y + 1) as u64;
+        let mut rng = StdRng::seed_from_u64(key);
+        let mut i = 0u64;
+        loop {
+            if self.noise < 1. {
+                let u: f64 = rng.gen();
+                let gap = (1. - u).ln() / (1. - self.noise).ln();
+                i = i.saturating_add(gap as u64);
+            }
+            if i >= area {
+                break;
+            }
+            let pos = min + IVec2::new((i % width) as i32, (i / width) as i32);
+            match cells.get(&pos) {
+                Some(_) => cells.remove(&pos),
+                None => cells.insert(pos, 1),
+            };
+            i += 1;
+        }
+    }
+}
+
+impl fmt::Display for Chances {
+    /// Formats the chances as the suffix of a rulestring, leaving out the ones that
+    /// always happen the way the rule says, and the whole suffix if they all do.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let default = Chances::default();
+        let parts = [
+            ('B', self.birth, default.birth),
+            ('S', self.survive, default.survive),
+            ('N', self.noise, default.noise),
+        ];
+        let parts = parts
+            .iter()
+            .filter(|(_, chance, default)| chance != default)
+            .map(|(c, chance, _)| format!("{}{}", c, chance))
+            .collect::<Vec<_>>();
+        if parts.is_empty() {
+            return Ok(());
+        }
+        write!(f, "~{}", parts.join(","))
+    }
+}
+
+/// A number between 0 and 1 for the cell at `pos`, the same whichever order
+/// cells are visited in.
+fn uniform(key: u64, pos: IVec2) -> f64 {
+    // SplitMix64's finaliser, to spread the position over every bit.
+    let mut z = key ^ (pos.x as u32 as u64) << 32 ^ pos.y as u32 as u64;
+    z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ z >> 30).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ z >> 27).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn bounding_box(cells: &HashMap<IVec2, u8>) -> Option<(IVec2, IVec2)> {
+    cells.keys().fold(None, |bounds, &pos| match bounds {
+        Some((min, max)) => Some((pos.min(min), pos.max(max))),
+        None => Some((pos, pos)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::GameRules;
+    use crate::universe::Universe;
+
+    /// The cells after running `rule` from a fixed soup for `generations`, `2^j`
+    /// at a time.
+    fn run(
+        rule: &str,
+        randomness: &mut Randomness,
+        j: u8,
+        generations: u64,
+    ) -> Vec<(i32, i32, u8)> {
+        let mut universe = Universe::new(GameRules::conway());
+        universe.set_rules(rule.parse().unwrap());
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..300 {
+            let pos = IVec2::new(rng.gen_range(-15..15), rng.gen_range(-15..15));
+            universe.set(pos, true);
+        }
+        while universe.generation() < generations {
+            universe.advance_pow2(j, randomness);
+        }
+        let mut cells = universe
+            .iter_states()
+            .map(|(pos, state)| (pos.x, pos.y, state))
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn seeds_replay() {
+        for rule in [
+            "B3/S23~B0.8,S0.9,N0.002",
+            "B2/S34/C4~S0.7",
+            "B3/S23~N0.01:T40,30",
+        ] {
+            let first = run(rule, &mut Randomness::new(42), 0, 32);
+            assert!(!first.is_empty(), "{} died out", rule);
+            assert_eq!(
+                run(rule, &mut Randomness::new(42), 0, 32),
+                first,
+                "{}",
+                rule
+            );
+            // However the run is split into steps.
+            assert_eq!(
+                run(rule, &mut Randomness::new(42), 3, 32),
+                first,
+                "{}",
+                rule
+            );
+            assert_ne!(
+                run(rule, &mut Randomness::new(43), 0, 32),
+                first,
+                "{}",
+                rule
+            );
+
+            let mut randomness = Randomness::new(42);
+            run(rule, &mut randomness, 0, 8);
+            randomness.reseed();
+            assert_eq!(run(rule, &mut randomness, 0, 32), first, "{}", rule);
+        }
+    }
+
+    #[test]
+    fn chances_round_trip() {
+        let (rule, chances) = Chances::split_suffix("B3/S23~B0.9,S0.95,N0.001").unwrap();
+        let chances = chances.unwrap();
+        assert_eq!(rule, "B3/S23");
+        assert_eq!(
+            (chances.birth, chances.survive, chances.noise),
+            (0.9, 0.95, 0.001)
+        );
+        assert_eq!(chances.to_string(), "~B0.9,S0.95,N0.001");
+        assert_eq!(
+            Chances::split_suffix("B3/S23~S0.5")
+                .unwrap()
+                .1
+                .unwrap()
+                .to_string(),
+            "~S0.5"
+        );
+        assert_eq!(Chances::default().to_string(), "");
+        assert!(Chances::split_suffix("B3/S23~B1.5").is_err());
+        assert!(Chances::split_suffix("B3/S23~X0.5").is_err());
+    }
+
+    #[test]
+    fn uniform_spreads_positions() {
+        let values = (0..100)
+            .flat_map(|y| (0..100).map(move |x| uniform(7, IVec2::new(x, y))))
+            .collect::<Vec<_>>();
+        assert!(values.iter().all(|u| (0. ..1.).contains(u)));
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        assert!((mean - 0.5).abs() < 0.01);
+        assert_ne!(uniform(7, IVec2::new(1, 0)), uniform(7, IVec2::new(0, 1)));
+    }
+}
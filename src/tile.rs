@@ -0,0 +1,130 @@
+use bevy::math::IVec2;
+
+/// The width and height of a tile in cells.
+pub const TILE_SIZE: i32 = 64;
+
+/// A 64x64 block of cells, one `u64` per row with bit `x` holding the cell at column `x`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tile {
+    rows: [u64; TILE_SIZE as usize],
+}
+
+impl Tile {
+    pub const EMPTY: Tile = Tile {
+        rows: [0; TILE_SIZE as usize],
+    };
+
+    /// Splits a cell position into the position of its tile and its position within it.
+    pub fn split(pos: IVec2) -> (IVec2, IVec2) {
+        (
+            IVec2::new(pos.x.div_euclid(TILE_SIZE), pos.y.div_euclid(TILE_SIZE)),
+            IVec2::new(pos.x.rem_euclid(TILE_SIZE), pos.y.rem_euclid(TILE_SIZE)),
+        )
+    }
+
+    pub fn get(&self, local: IVec2) -> bool {
+        self.rows[local.y as usize] & (1 << local.x) != 0
+    }
+
+    pub fn set(&mut self, local: IVec2, alive: bool) {
+        if alive {
+            self.rows[local.y as usize] |= 1 << local.x;
+        } else {
+            self.rows[local.y as usize] &= !(1 << local.x);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.iter().all(|&row| row == 0)
+    }
+
+    pub fn population(&self) -> usize {
+        self.rows.iter().map(|row| row.count_ones() as usize).sum()
+    }
+
+    /// The local positions of every alive cell in the tile.
+    pub fn iter_alive(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.rows.iter().enumerate().flat_map(|(y, &row)| {
+            (0..TILE_SIZE)
+                .filter(move |x| row & (1 << x) != 0)
+                .map(move |x| IVec2::new(x, y as i32))
+        })
+    }
+
+    /// The offsets of the neighbouring tiles that could have cells born in them
+    /// next generation, because this tile has alive cells on the shared edge.
+    pub fn spills(&self) -> impl Iterator<Item = IVec2> {
+        const LEFT: u64 = 1;
+        const RIGHT: u64 = 1 << (TILE_SIZE - 1);
+        let bottom = self.rows[0];
+        let top = self.rows[TILE_SIZE as usize - 1];
+        let columns = self.rows.iter().fold(0, |acc, row| acc | row);
+
+        [
+            (IVec2::new(0, -1), bottom != 0),
+            (IVec2::new(0, 1), top != 0),
+            (IVec2::new(-1, 0), columns & LEFT != 0),
+            (IVec2::new(1, 0), columns & RIGHT != 0),
+            (IVec2::new(-1, -1), bottom & LEFT != 0),
+            (IVec2::new(1, -1), bottom & RIGHT != 0),
+            (IVec2::new(-1, 1), top & LEFT != 0),
+            (IVec2::new(1, 1), top & RIGHT != 0),
+        ]
+        .into_iter()
+        .filter_map(|(offset, spills)| spills.then_some(offset))
+    }
+
+    /// Computes the next generation of the middle tile of `neighbourhood`, indexed
+    /// `[dy + 1][dx + 1]`, a whole row at a time.
+    ///
+    /// `birth` and `survive` have bit `n` set if a cell with `n` alive neighbours
+    /// is born or survives.
+    pub fn step(neighbourhood: [[&Tile; 3]; 3], birth: u16, survive: u16) -> Tile {
+        // The west, middle and east words of row `y`, which can be outside `0..64`.
+        let row = |y: i32| {
+            let tiles = &neighbourhood[(y.div_euclid(TILE_SIZE) + 1) as usize];
+            let y = y.rem_euclid(TILE_SIZE) as usize;
+            (tiles[0].rows[y], tiles[1].rows[y], tiles[2].rows[y])
+        };
+
+        let mut next = Tile::EMPTY;
+        for y in 0..TILE_SIZE {
+            // Count the neighbours of all 64 cells at once, each bit of `counts[i]`
+            // is bit `i` of that cell's count.
+            let mut counts = [0u64; 4];
+            let mut add = |mut carry: u64| {
+                for plane in counts.iter_mut() {
+                    let overflow = *plane & carry;
+                    *plane ^= carry;
+                    carry = overflow;
+                }
+            };
+            for dy in -1..=1 {
+                let (west, middle, east) = row(y + dy);
+                add((middle << 1) | (west >> (TILE_SIZE - 1)));
+                add((middle >> 1) | (east << (TILE_SIZE - 1)));
+                if dy != 0 {
+                    add(middle);
+                }
+            }
+
+            let mut born = 0;
+            let mut stays = 0;
+            for n in (0..=8).filter(|n| (birth | survive) & (1 << n) != 0) {
+                let matches = counts.iter().enumerate().fold(!0, |acc, (bit, &plane)| {
+                    acc & if n & (1 << bit) != 0 { plane } else { !plane }
+                });
+                if birth & (1 << n) != 0 {
+                    born |= matches;
+                }
+                if survive & (1 << n) != 0 {
+                    stays |= matches;
+                }
+            }
+
+            let alive = neighbourhood[1][1].rows[y as usize];
+            next.rows[y as usize] = (alive & stays) | (!alive & born);
+        }
+        next
+    }
+}
@@ -0,0 +1,831 @@
+use bevy::math::IVec2;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::automaton::{self, Automaton};
+use crate::continuous::{self, Field};
+use crate::hashlife::HashLife;
+use crate::ltl;
+use crate::margolus;
+use crate::neighbourhood::Neighbourhood;
+use crate::rules::GameRules;
+use crate::ruletable;
+use crate::stochastic::Randomness;
+use crate::tile::{Tile, TILE_SIZE};
+use crate::wolfram::{self, Rows};
+
+/// The largest step exponent `advance` will accept, beyond it patterns can
+/// move further than an `i32` can address in one jump.
+pub const MAX_STEP_EXPONENT: u8 = 30;
+
+/// The largest step exponent of engines that step one generation at a time,
+/// beyond it a single step takes long enough to freeze the app.
+pub const MAX_STEPPED_EXPONENT: u8 = 8;
+
+/// The algorithm a `Universe` uses to compute new generations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    /// Bit-packed tiles stepped one generation at a time, best for chaotic patterns.
+    Tiles,
+    /// A memoized quadtree, best for jumping far ahead with regular patterns.
+    HashLife,
+    /// The state of every cell that isn't dead, stepped a cell at a time. It's the
+    /// slowest, but the only one that runs rules with more than two states.
+    Sparse,
+    /// The value of every cell of a bounded grid, the only one that runs
+    /// continuous rules.
+    Continuous,
+}
+
+impl Engine {
+    /// The largest step exponent `advance` uses with the engine, only HashLife
+    /// jumps ahead without going through every generation.
+    pub fn max_step_exponent(self) -> u8 {
+        match self {
+            Engine::HashLife => MAX_STEP_EXPONENT,
+            Engine::Tiles | Engine::Sparse | Engine::Continuous => MAX_STEPPED_EXPONENT,
+        }
+    }
+
+    /// Whether the engine can run `rules`.
+    pub fn supports(self, rules: &GameRules) -> bool {
+        match self {
+            Engine::Continuous => rules.is_continuous(),
+            _ if rules.is_continuous() => false,
+            Engine::Sparse => true,
+            // HashLife jumps ahead with the same rule, `B0` rules change it every generation.
+            Engine::HashLife if rules.has_b0() => false,
+            Engine::Tiles | Engine::HashLife => {
+                rules.states() == 2
+                    && rules.masks().is_some()
+                    && *rules.neighbourhood() == Neighbourhood::Moore
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Cells {
+    Tiles(HashMap<IVec2, Box<Tile>>),
+    HashLife(Box<HashLife>),
+    Sparse(HashMap<IVec2, u8>),
+    Continuous(Box<Field>),
+}
+
+/// An unbounded grid of cells and the rules that evolve it.
+///
+/// This doesn't know anything about the ECS, so it can be stepped without an `App`.
+#[derive(Debug, Clone)]
+pub struct Universe {
+    cells: Cells,
+    rules: GameRules,
+    /// Whether every cell away from the pattern is alive, the cells are then
+    /// stored inverted.
+    background: bool,
+    /// Where the spacetime diagram of a one dimensional rule is up to.
+    rows: Rows,
+    /// Whether the blocks of a block rule start at odd coordinates, which they do
+    /// every other generation from when the rule was set.
+    block_phase: bool,
+    generation: u64,
+    step_exponent: u8,
+}
+
+impl Universe {
+    pub fn new(rules: GameRules) -> Self {
+        Self {
+            cells: Cells::Tiles(HashMap::default()),
+            rules,
+            background: false,
+            rows: Rows::default(),
+            block_phase: false,
+            generation: 0,
+            step_exponent: 0,
+        }
+    }
+
+    pub fn get(&self, pos: IVec2) -> bool {
+        let stored = match &self.cells {
+            Cells::Tiles(tiles) => {
+                let (tile, local) = Tile::split(pos);
+                tiles.get(&tile).is_some_and(|t| t.get(local))
+            }
+            Cells::HashLife(life) => life.get(pos),
+            Cells::Sparse(_) | Cells::Continuous(_) => return self.state(pos) != 0,
+        };
+        stored != self.background
+    }
+
+    pub fn set(&mut self, pos: IVec2, alive: bool) {
+        let stored = alive != self.background;
+        match &mut self.cells {
+            Cells::Sparse(_) => self.set_state(pos, alive as u8),
+            Cells::Tiles(tiles) => {
+                let (tile, local) = Tile::split(pos);
+                if stored {
+                    tiles
+                        .entry(tile)
+                        .or_insert_with(|| Box::new(Tile::EMPTY))
+                        .set(local, true);
+                } else if let Some(t) = tiles.get_mut(&tile) {
+                    t.set(local, false);
+                    if t.is_empty() {
+                        tiles.remove(&tile);
+                    }
+                }
+            }
+            Cells::HashLife(life) => life.set(pos, stored),
+            Cells::Continuous(field) => field.set(pos, alive as u8 as f32),
+        }
+    }
+
+    /// The state of the cell at `pos`, `0` is dead and `1` alive. The values of
+    /// continuous cells are rounded to one of `continuous::LEVELS` states.
+    pub fn state(&self, pos: IVec2) -> u8 {
+        match &self.cells {
+            Cells::Sparse(cells) => {
+                let state = cells.get(&pos).copied();
+                state.unwrap_or_else(|| self.rows.background(pos.y)) ^ self.background as u8
+            }
+            Cells::Continuous(field) => level(field.get(pos)),
+            _ => self.get(pos) as u8,
+        }
+    }
+
+    /// How alive the cell at `pos` is, from `0` for dead to `1` for alive or the
+    /// last state of the rule.
+    pub fn value(&self, pos: IVec2) -> f32 {
+        match &self.cells {
+            Cells::Continuous(field) => field.get(pos),
+            _ => self.state(pos) as f32 / (self.rules.states() - 1) as f32,
+        }
+    }
+
+    /// Sets the state of the cell at `pos`, engines that only know alive and
+    /// dead treat any state other than `0` as alive.
+    pub fn set_state(&mut self, pos: IVec2, state: u8) {
+        match &mut self.cells {
+            // Only two state rules run with an alive background.
+            Cells::Sparse(cells) => match state ^ self.background as u8 {
+                stored if stored == self.rows.background(pos.y) => {
+                    cells.remove(&pos);
+                }
+                stored => {
+                    cells.insert(pos, stored);
+                }
+            },
+            Cells::Continuous(field) => {
+                field.set(pos, state as f32 / (continuous::LEVELS - 1) as f32);
+            }
+            _ => self.set(pos, state != 0),
+        }
+    }
+
+    /// Makes every cell that's alive in `life` alive. An empty universe whose
+    /// rules HashLife can run takes the tree over as it is, so patterns read as a
+    /// tree are never gone through a cell at a time.
+    pub fn add_tree(&mut self, mut life: HashLife) {
+        match self.rules.masks() {
+            Some((birth, survive))
+                if self.population() == 0
+                    && !self.background
+                    && Engine::HashLife.supports(&self.rules) =>
+            {
+                life.set_rule(birth, survive);
+                self.cells = Cells::HashLife(Box::new(life));
+            }
+            _ => {
+                for pos in life.iter_alive() {
+                    self.set(pos, true);
+                }
+            }
+        }
+    }
+
+    /// Whether every cell away from the pattern is alive, after a generation of a
+    /// rule with `B0`. The cells that are stored are then the dead ones, they're
+    /// the ones `iter_states`, `iter_alive`, `bounding_box` and `population` count.
+    pub fn background(&self) -> bool {
+        self.background
+    }
+
+    /// While the background is alive, the universe of the complement rule whose
+    /// alive cells are the ones stored here, which is how patterns are saved.
+    pub fn complement(&self) -> Option<Universe> {
+        if !self.background {
+            return None;
+        }
+        let mut universe = self.clone();
+        universe.rules = self.rules.complement()?;
+        universe.background = false;
+        Some(universe)
+    }
+
+    /// Every cell that isn't dead, along with its state.
+    pub fn iter_states(&self) -> Box<dyn Iterator<Item = (IVec2, u8)> + '_> {
+        match &self.cells {
+            // Rows of a one dimensional rule with an alive background store their
+            // dead cells too.
+            Cells::Sparse(cells) => Box::new(
+                cells
+                    .iter()
+                    .map(|(&pos, &state)| (pos, state))
+                    .filter(|&(_, state)| state != 0),
+            ),
+            Cells::Continuous(field) => Box::new(
+                field
+                    .iter()
+                    .map(|(pos, value)| (pos, level(value)))
+                    .filter(|&(_, state)| state != 0),
+            ),
+            _ => Box::new(self.iter_alive().map(|pos| (pos, 1))),
+        }
+    }
+
+    pub fn iter_alive(&self) -> Box<dyn Iterator<Item = IVec2> + '_> {
+        match &self.cells {
+            Cells::Tiles(tiles) => {
+                Box::new(tiles.iter().flat_map(|(&tile, t)| {
+                    t.iter_alive().map(move |local| tile * TILE_SIZE + local)
+                }))
+            }
+            Cells::HashLife(life) => Box::new(life.iter_alive()),
+            Cells::Sparse(_) | Cells::Continuous(_) => {
+                Box::new(self.iter_states().map(|(pos, _)| pos))
+            }
+        }
+    }
+
+    /// The inclusive minimum and maximum corners of the alive cells, if there are any.
+    pub fn bounding_box(&self) -> Option<(IVec2, IVec2)> {
+        self.iter_alive().fold(None, |bounds, pos| match bounds {
+            Some((min, max)) => Some((pos.min(min), pos.max(max))),
+            None => Some((pos, pos)),
+        })
+    }
+
+    pub fn population(&self) -> u64 {
+        match &self.cells {
+            Cells::Tiles(tiles) => tiles.values().map(|t| t.population() as u64).sum(),
+            Cells::HashLife(life) => life.population(),
+            Cells::Sparse(_) | Cells::Continuous(_) => self.iter_states().count() as u64,
+        }
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Sets the generation the pattern is at, for patterns saved partway through a run.
+    pub fn set_generation(&mut self, generation: u64) {
+        self.generation = generation;
+    }
+
+    /// The HashLife tree the cells are kept in, when that's the engine.
+    pub(crate) fn tree(&self) -> Option<&HashLife> {
+        match &self.cells {
+            Cells::HashLife(life) => Some(life),
+            _ => None,
+        }
+    }
+
+    pub fn rules(&self) -> &GameRules {
+        &self.rules
+    }
+
+    /// Changes the rules, moving over to an engine that can run them if the
+    /// current one can't. Sparse universes go back to tiles when they can.
+    ///
+    /// Rules that can't run with an alive background drop it, the cells that are
+    /// really alive are kept on the grid or within the bounding box of the
+    /// pattern and every other cell dies.
+    pub fn set_rules(&mut self, rules: GameRules) {
+        if self.background && !rules.runs_inverted() {
+            self.drop_background();
+        }
+        if !matches!(rules.base(), GameRules::Wolfram(_)) {
+            self.drop_rows();
+        }
+        if let (Cells::HashLife(life), Some((birth, survive))) = (&mut self.cells, rules.masks()) {
+            life.set_rule(birth, survive);
+        }
+        if self.rules.base() != rules.base() {
+            self.block_phase = false;
+        }
+        let grid = self.rules.grid().cloned();
+        let states = self.rules.states();
+        self.rules = rules;
+        if self.rules.is_continuous() {
+            // The field covers the grid, so it's made again if that changes.
+            if self.engine() != Engine::Continuous || self.rules.grid() != grid.as_ref() {
+                self.move_cells(Engine::Continuous, states);
+            }
+        } else if !self.engine().supports(&self.rules) {
+            // Go straight to tiles when the rules allow it, rather than via sparse.
+            if Engine::Tiles.supports(&self.rules) {
+                self.set_engine(Engine::Tiles);
+            } else {
+                self.set_engine(Engine::Sparse);
+            }
+        } else if self.engine() == Engine::Sparse && Engine::Tiles.supports(&self.rules) {
+            self.set_engine(Engine::Tiles);
+        }
+    }
+
+    pub fn engine(&self) -> Engine {
+        match self.cells {
+            Cells::Tiles(_) => Engine::Tiles,
+            Cells::HashLife(_) => Engine::HashLife,
+            Cells::Sparse(_) => Engine::Sparse,
+            Cells::Continuous(_) => Engine::Continuous,
+        }
+    }
+
+    /// Moves every cell that isn't dead over to `engine`, if it can run the rules.
+    pub fn set_engine(&mut self, engine: Engine) {
+        // HashLife can't step the inverted cells of an alive background either.
+        let background = engine == Engine::HashLife && self.background;
+        if engine == self.engine() || !engine.supports(&self.rules) || background {
+            return;
+        }
+        self.move_cells(engine, self.rules.states());
+    }
+
+    /// Moves every cell that isn't dead over to `engine`, from states of rules
+    /// with `states` of them. Cells go between continuous and other engines by
+    /// how alive they are, so alive cells become `1` and back.
+    fn move_cells(&mut self, engine: Engine, states: u8) {
+        let cells = match &self.cells {
+            Cells::Continuous(field) => field.iter().filter(|&(_, value)| value > 0.).collect(),
+            _ => self
+                .iter_states()
+                .map(|(pos, state)| (pos, state_value(state, states)))
+                .collect::<Vec<_>>(),
+        };
+        self.cells = self.empty_cells(engine);
+        // The cells are moved the way they're stored, not inverted again.
+        let background = std::mem::take(&mut self.background);
+        let states = self.rules.states();
+        for (pos, value) in cells {
+            match &mut self.cells {
+                Cells::Continuous(field) => field.set(pos, value),
+                _ => self.set_state(pos, value_state(value, states)),
+            }
+        }
+        self.background = background;
+        self.set_step_exponent(self.step_exponent);
+    }
+
+    /// How many generations `advance` moves forward, as a power of two.
+    pub fn step_exponent(&self) -> u8 {
+        self.step_exponent
+    }
+
+    /// Sets the step exponent, up to the most the engine allows.
+    pub fn set_step_exponent(&mut self, step_exponent: u8) {
+        self.step_exponent = step_exponent.min(self.engine().max_step_exponent());
+    }
+
+    /// Stores the alive cells as they are rather than inverted, the ones on a
+    /// finite grid or within the bounding box of the stored cells.
+    fn drop_background(&mut self) {
+        let bounds = match self.rules.grid() {
+            Some(grid) if grid.width > 0 && grid.height > 0 => Some(grid.bounds()),
+            _ => self.bounding_box(),
+        };
+        let alive = bounds.map_or_else(Vec::new, |(min, max)| {
+            (min.y..=max.y)
+                .flat_map(|y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+                .filter(|&pos| self.get(pos))
+                .collect()
+        });
+        self.cells = self.empty_cells(self.engine());
+        self.background = false;
+        for pos in alive {
+            self.set(pos, true);
+        }
+    }
+
+    /// Forgets the rows of a one dimensional rule, every cell of a row that came
+    /// alive away from the pattern dies.
+    fn drop_rows(&mut self) {
+        if let Cells::Sparse(cells) = &mut self.cells {
+            cells.retain(|_, state| *state != 0);
+        }
+        self.rows = Rows::default();
+    }
+
+    /// The row the next generation of a one dimensional rule is computed from,
+    /// once it's been stepped.
+    pub fn newest_row(&self) -> Option<i32> {
+        self.rows.newest()
+    }
+
+    /// Kills every cell and resets the generation count, the rules are kept.
+    pub fn clear(&mut self) {
+        self.cells = self.empty_cells(self.engine());
+        self.background = false;
+        self.rows = Rows::default();
+        self.block_phase = false;
+        self.generation = 0;
+    }
+
+    /// Advances the universe by exactly one generation, with `randomness` making
+    /// the choices of stochastic rules.
+    ///
+    /// The next generation is computed entirely from the current one before it's
+    /// replaced, so the result doesn't depend on the order cells are visited in.
+    pub fn step(&mut self, randomness: &mut Randomness) {
+        self.advance_pow2(0, randomness);
+    }
+
+    /// Advances the universe by `2^step_exponent` generations.
+    pub fn advance(&mut self, randomness: &mut Randomness) {
+        self.advance_pow2(self.step_exponent, randomness);
+    }
+
+    /// Advances the universe by `2^j` generations, with `j` capped at the most
+    /// the engine allows.
+    pub fn advance_pow2(&mut self, j: u8, randomness: &mut Randomness) {
+        let j = j.min(self.engine().max_step_exponent());
+        match &mut self.cells {
+            Cells::Tiles(tiles) => {
+                for _ in 0..(1u64 << j) {
+                    let (rules, background) = self.rules.relative_to(self.background);
+                    self.background = background;
+                    // Only the sparse engine runs rules without masks.
+                    let (birth, survive) = rules.masks().unwrap_or_default();
+                    *tiles = step_tiles(tiles, birth, survive);
+                }
+            }
+            Cells::HashLife(life) => life.advance(j),
+            Cells::Continuous(field) => {
+                if let GameRules::Continuous(rule) = self.rules.base() {
+                    for _ in 0..(1u64 << j) {
+                        field.step(rule);
+                    }
+                }
+            }
+            Cells::Sparse(cells) => {
+                for _ in 0..(1u64 << j) {
+                    // Drawn whatever the rule, so a seed replays the same way
+                    // however the run is split into steps.
+                    let key = randomness.next_key();
+                    let (rules, background) = self.rules.relative_to(self.background);
+                    self.background = background;
+                    *cells = match &*rules {
+                        GameRules::Wolfram(rule) => {
+                            self.rows.start(cells);
+                            wolfram::step(cells, rule, &self.rows);
+                            self.rows.advance(rule);
+                            continue;
+                        }
+                        GameRules::Bounded { rules, grid } => match &**rules {
+                            GameRules::Named(Automaton::LangtonsAnt) => {
+                                automaton::step_ants(cells, |pos| grid.wrap(pos))
+                            }
+                            GameRules::Wolfram(rule) => {
+                                self.rows.start(cells);
+                                let rows = &self.rows;
+                                let next = grid.step(cells, reach(rules), |cells| {
+                                    let mut cells = cells.clone();
+                                    wolfram::step(&mut cells, rule, rows);
+                                    cells
+                                });
+                                self.rows.advance(rule);
+                                next
+                            }
+                            _ => grid.step(cells, reach(rules), |cells| {
+                                step_unbounded(cells, rules, self.block_phase, key)
+                            }),
+                        },
+                        rules => step_unbounded(cells, rules, self.block_phase, key),
+                    };
+                    let rules = match &*rules {
+                        GameRules::Bounded { rules, .. } => rules,
+                        rules => rules,
+                    };
+                    if let GameRules::Stochastic { chances, .. } = rules {
+                        chances.add_noise(cells, self.rules.grid(), key);
+                    }
+                    self.block_phase = !self.block_phase;
+                }
+            }
+        }
+        self.generation += 1 << j;
+    }
+
+    fn empty_cells(&self, engine: Engine) -> Cells {
+        match engine {
+            Engine::Tiles => Cells::Tiles(HashMap::default()),
+            Engine::HashLife => {
+                let (birth, survive) = self.rules.masks().unwrap_or_default();
+                Cells::HashLife(Box::new(HashLife::new(birth, survive)))
+            }
+            Engine::Sparse => Cells::Sparse(HashMap::default()),
+            Engine::Continuous => {
+                let grid = self.rules.grid().unwrap_or(&continuous::DEFAULT_GRID);
+                Cells::Continuous(Box::new(Field::new(grid)))
+            }
+        }
+    }
+}
+
+/// The state a continuous cell with `value` is read as.
+fn level(value: f32) -> u8 {
+    (value * (continuous::LEVELS - 1) as f32).round() as u8
+}
+
+/// How alive a cell in `state` is, out of `states`. State `1` is alive and the
+/// rest are dying, each one less alive than the one before.
+fn state_value(state: u8, states: u8) -> f32 {
+    match state {
+        0 => 0.,
+        1 => 1.,
+        state => (states - state) as f32 / (states - 1) as f32,
+    }
+}
+
+/// The state out of `states` closest to being `value` alive, undoing `state_value`.
+fn value_state(value: f32, states: u8) -> u8 {
+    let last = states - 1;
+    match (value * last as f32).round() as u8 {
+        0 => 0,
+        n if n >= last => 1,
+        n => states - n,
+    }
+}
+
+/// Computes the next generation of every tile that could have alive cells in it.
+fn step_tiles(
+    tiles: &HashMap<IVec2, Box<Tile>>,
+    birth: u16,
+    survive: u16,
+) -> HashMap<IVec2, Box<Tile>> {
+    // Every tile with alive cells, plus the neighbouring tiles they could spill into.
+    let active = tiles
+        .iter()
+        .flat_map(|(&pos, tile)| std::iter::once(pos).chain(tile.spills().map(move |o| pos + o)))
+        .collect::<HashSet<_>>();
+
+    let tile = |pos: IVec2| tiles.get(&pos).map_or(&Tile::EMPTY, |t| t);
+    active
+        .into_iter()
+        .filter_map(|pos| {
+            let neighbourhood =
+                [-1, 0, 1].map(|dy| [-1, 0, 1].map(|dx| tile(pos + IVec2::new(dx, dy))));
+            let next = Tile::step(neighbourhood, birth, survive);
+            (!next.is_empty()).then(|| (pos, Box::new(next)))
+        })
+        .collect()
+}
+
+/// Computes the next generation of `cells` with the stepper for `rules`, the
+/// blocks of block rules offset if `block_phase`. Stochastic rules make their
+/// choices from `key`.
+fn step_unbounded(
+    cells: &HashMap<IVec2, u8>,
+    rules: &GameRules,
+    block_phase: bool,
+    key: u64,
+) -> HashMap<IVec2, u8> {
+    match rules {
+        GameRules::LargerThanLife(rule) => ltl::step(cells, rule),
+        GameRules::Named(Automaton::LangtonsAnt) => automaton::step_ants(cells, Some),
+        GameRules::Table(table) => ruletable::step(cells, table),
+        GameRules::Margolus(rule) => margolus::step(cells, rule, block_phase),
+        GameRules::Stochastic { rules, chances } => {
+            let next = step_unbounded(cells, rules, block_phase, key);
+            chances.apply(cells, next, rules.states(), key)
+        }
+        rules => step_sparse(cells, rules),
+    }
+}
+
+/// How far away the cells that decide the next state of a cell can be.
+fn reach(rules: &GameRules) -> i32 {
+    match rules {
+        GameRules::LargerThanLife(rule) => rule.range as i32,
+        rules => rules
+            .neighbourhood()
+            .offsets()
+            .iter()
+            .map(|&(x, y)| x.abs().max(y.abs()))
+            .max()
+            .unwrap_or(0),
+    }
+}
+
+/// Computes the next state of every cell that isn't dead and of their neighbours.
+fn step_sparse(cells: &HashMap<IVec2, u8>, rules: &GameRules) -> HashMap<IVec2, u8> {
+    // Bit `i` of a cell's neighbours is set if the cell at `offsets[i]` from it is alive,
+    // so each alive cell is that neighbour of the cell `offsets[i]` away the other way.
+    let offsets = rules.neighbourhood().offsets();
+    let mut alive_neighbours: HashMap<IVec2, u32> = HashMap::default();
+    for (&pos, _) in cells.iter().filter(|(_, &state)| state == 1) {
+        for (i, &offset) in offsets.iter().enumerate() {
+            *alive_neighbours
+                .entry(pos - IVec2::from(offset))
+                .or_default() |= 1 << i;
+        }
+    }
+
+    // Dying cells change state even with no alive neighbours.
+    let dying = cells
+        .keys()
+        .filter(|pos| !alive_neighbours.contains_key(pos))
+        .map(|&pos| (pos, 0));
+    alive_neighbours
+        .iter()
+        .map(|(&pos, &neighbours)| (pos, neighbours))
+        .chain(dying)
+        .filter_map(|(pos, neighbours)| {
+            let state = cells.get(&pos).copied().unwrap_or(0);
+            let next = rules.next_state(state, neighbours);
+            (next != 0).then_some((pos, next))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+
+    /// A universe running `rule` on `engine` from a random soup around the origin.
+    fn soup(rule: &str, engine: Engine, size: i32) -> Universe {
+        let mut universe = Universe::new(GameRules::conway());
+        universe.set_rules(rule.parse().unwrap());
+        universe.set_engine(engine);
+        assert_eq!(universe.engine(), engine, "{}", rule);
+        let mut rng = StdRng::seed_from_u64(5);
+        for y in 0..size {
+            for x in 0..size {
+                if rng.gen_bool(0.4) {
+                    universe.set(IVec2::new(x - size / 2, y - size / 2), true);
+                }
+            }
+        }
+        universe
+    }
+
+    fn sorted(universe: &Universe) -> Vec<(i32, i32, u8)> {
+        let mut cells = universe
+            .iter_states()
+            .map(|(pos, state)| (pos.x, pos.y, state))
+            .collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn engines_agree() {
+        let mut randomness = Randomness::new(0);
+        for rule in ["B3/S23", "B36/S23"] {
+            let mut universes = [Engine::Tiles, Engine::HashLife, Engine::Sparse]
+                .map(|engine| soup(rule, engine, 40));
+            for generation in 1..=64 {
+                for universe in &mut universes {
+                    universe.step(&mut randomness);
+                }
+                assert_eq!(
+                    sorted(&universes[0]),
+                    sorted(&universes[1]),
+                    "{}",
+                    generation
+                );
+                assert_eq!(
+                    sorted(&universes[0]),
+                    sorted(&universes[2]),
+                    "{}",
+                    generation
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn switching_engines_keeps_cells() {
+        let mut universe = soup("B3/S23", Engine::Tiles, 30);
+        let cells = sorted(&universe);
+        for engine in [Engine::HashLife, Engine::Sparse, Engine::Tiles] {
+            universe.set_engine(engine);
+            assert_eq!(sorted(&universe), cells, "{:?}", engine);
+        }
+        universe.set_rules("B2/S/C3".parse().unwrap());
+        assert_eq!(universe.engine(), Engine::Sparse);
+        assert_eq!(sorted(&universe), cells);
+    }
+
+    #[test]
+    fn b0_strobes() {
+        let mut randomness = Randomness::new(0);
+        for rule in ["B013/S23", "B0123478/S34678", "B02a3/S23-a"] {
+            let rules = rule.parse::<GameRules>().unwrap();
+            let engines = if Engine::Tiles.supports(&rules) {
+                vec![Engine::Tiles, Engine::Sparse]
+            } else {
+                vec![Engine::Sparse]
+            };
+            for engine in engines {
+                let mut universe = soup(rule, engine, 12);
+                // Every cell of a window the pattern can't reach the edge of, with
+                // every cell outside it in the background's state.
+                let (min, size) = (IVec2::splat(-40), 80);
+                let mut window = (0..size * size)
+                    .map(|i| universe.get(min + IVec2::new(i % size, i / size)) as u8)
+                    .collect::<Vec<_>>();
+                let mut background = 0;
+                let offsets = rules.neighbourhood().offsets();
+                for generation in 1..=20 {
+                    let cell = |x: i32, y: i32| {
+                        if (0..size).contains(&x) && (0..size).contains(&y) {
+                            window[(y * size + x) as usize]
+                        } else {
+                            background
+                        }
+                    };
+                    let neighbours = |x: i32, y: i32| {
+                        offsets
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, &(dx, dy))| cell(x + dx, y + dy) == 1)
+                            .map(|(i, _)| 1 << i)
+                            .sum()
+                    };
+                    let next = (0..size * size)
+                        .map(|i| {
+                            let (x, y) = (i % size, i / size);
+                            rules.next_state(cell(x, y), neighbours(x, y))
+                        })
+                        .collect();
+                    background = rules.next_state(background, neighbours(-size, -size));
+                    window = next;
+
+                    universe.step(&mut randomness);
+                    assert_eq!(universe.background(), background == 1);
+                    for (i, &state) in window.iter().enumerate() {
+                        let pos = min + IVec2::new(i as i32 % size, i as i32 / size);
+                        assert_eq!(
+                            universe.get(pos),
+                            state == 1,
+                            "{} on {:?} at {:?} after {}",
+                            rule,
+                            engine,
+                            pos,
+                            generation
+                        );
+                    }
+                    let far = IVec2::new(1000, -1000);
+                    assert_eq!(universe.get(far), background == 1);
+                }
+            }
+        }
+        // Without `S8` the background goes back and forth every generation.
+        let mut universe = soup("B013/S23", Engine::Tiles, 12);
+        for generation in 1..=6 {
+            universe.step(&mut randomness);
+            assert_eq!(universe.background(), generation % 2 == 1);
+        }
+        // Rules that can't run inverted leave the alive cells on a dead background.
+        universe.step(&mut randomness);
+        universe.set_rules("B2/S/C3".parse().unwrap());
+        assert!(!universe.background());
+        assert!(!universe.get(IVec2::new(1000, -1000)));
+    }
+
+    #[test]
+    fn step_exponent_is_capped() {
+        let mut universe = soup("B3/S23", Engine::HashLife, 10);
+        universe.set_step_exponent(20);
+        assert_eq!(universe.step_exponent(), 20);
+        universe.set_engine(Engine::Tiles);
+        assert_eq!(universe.step_exponent(), MAX_STEPPED_EXPONENT);
+
+        // Jumps are capped however they're asked for.
+        universe.advance_pow2(20, &mut Randomness::new(0));
+        assert_eq!(universe.generation(), 1 << MAX_STEPPED_EXPONENT);
+    }
+
+    #[test]
+    fn brians_brain() {
+        let rules = "B2/S/C3".parse::<GameRules>().unwrap();
+        let mut universe = Universe::new(rules);
+        universe.set_rules("B2/S/C3".parse().unwrap());
+        assert_eq!(universe.engine(), Engine::Sparse);
+        // Two cells side by side make a pair of cells either side, then die out
+        // through the dying state.
+        universe.set(IVec2::new(0, 0), true);
+        universe.set(IVec2::new(1, 0), true);
+        universe.step(&mut Randomness::new(0));
+        assert_eq!(universe.state(IVec2::new(0, 0)), 2);
+        assert_eq!(universe.state(IVec2::new(1, 0)), 2);
+        for pos in [(0, 1), (1, 1), (0, -1), (1, -1)] {
+            assert_eq!(universe.state(IVec2::from(pos)), 1, "{:?}", pos);
+        }
+        universe.step(&mut Randomness::new(0));
+        assert_eq!(universe.state(IVec2::new(0, 0)), 0);
+        assert_eq!(universe.state(IVec2::new(0, 1)), 2);
+    }
+}
@@ -1,5 +1,11 @@
 use bevy::prelude::*;
 
+use automata::rules::GameRules;
+use automata::universe::Universe;
+
+/// How many rows are left below the newest row of a one dimensional rule.
+const SPACETIME_MARGIN: i32 = 4;
+
 #[derive(Component)]
 pub struct View {
     pub offset: IVec2,
@@ -22,6 +28,22 @@ fn move_view(mut query: Query<&mut View>, keyboard: Res<Input<KeyCode>>) {
     }
 }
 
+/// Scrolls down with the newest row of a one dimensional rule, so its spacetime
+/// diagram scrolls up the window as it grows.
+fn follow_newest_row(universe: Res<Universe>, mut query: Query<&mut View>) {
+    if !universe.is_changed() {
+        return;
+    }
+    if !matches!(universe.rules().base(), GameRules::Wolfram(_)) {
+        return;
+    }
+    if let Some(newest) = universe.newest_row() {
+        for mut view in query.iter_mut() {
+            view.offset.y = view.offset.y.min(newest - SPACETIME_MARGIN);
+        }
+    }
+}
+
 pub fn startup_system(mut commands: Commands, windows: Res<Windows>) {
     let window = windows.primary();
     let cell_size = Vec2::splat(4.);
@@ -39,6 +61,8 @@ pub fn startup_system(mut commands: Commands, windows: Res<Windows>) {
 pub(crate) struct ViewPlugin;
 impl Plugin for ViewPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(move_view).add_startup_system(startup_system);
+        app.add_system(move_view)
+            .add_system(follow_newest_row)
+            .add_startup_system(startup_system);
     }
 }
@@ -0,0 +1,343 @@
+//! One dimensional automata numbered like Wolfram does, the
+//! [elementary](https://conwaylife.com/wiki/Elementary_cellular_automaton) rules like
+//! `W30` and totalistic ones with more states and a bigger range like `C1635,K3,R1`.
+//!
+//! The universe is drawn as a spacetime diagram, each generation is a row below
+//! the one before it. Rules where a row of dead cells comes alive change the
+//! state of every cell of a row away from the pattern, so each row has its own
+//! background and only the cells that differ from it are stored.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use bevy::math::IVec2;
+use bevy::utils::{HashMap, HashSet};
+
+use crate::neighbourhood::Neighbourhood;
+use crate::rules::RuleParseError;
+
+/// How many rows, including the newest, are kept. Older ones are forgotten so
+/// the universe doesn't keep growing.
+pub const HISTORY: i32 = 512;
+
+/// The most states and the biggest range of a totalistic rule.
+const MAX_STATES: u8 = 36;
+const MAX_RADIUS: u8 = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WolframRule {
+    /// The rule number of an elementary rule or the code of a totalistic one.
+    pub code: u64,
+    /// How many states cells have, `k`.
+    pub states: u8,
+    /// How many cells either side of a cell are its neighbours, `r`.
+    pub radius: u8,
+    /// Whether the next state only depends on the sum of the states of the cell
+    /// and its neighbours, rather than the arrangement of the three.
+    pub totalistic: bool,
+    /// The neighbours in the row, from the furthest left to the furthest right.
+    pub neighbourhood: Neighbourhood,
+    /// The state a cell moves to for each arrangement or each sum.
+    next: Vec<u8>,
+}
+
+impl WolframRule {
+    /// The elementary rule with Wolfram code `number`.
+    pub fn elementary(number: u8) -> Result<WolframRule, RuleParseError> {
+        WolframRule::new(number as u64, 2, 1, false)
+    }
+
+    /// The totalistic rule with `states` states and `radius` neighbours either side
+    /// whose code is `code`.
+    pub fn totalistic(code: u64, states: u8, radius: u8) -> Result<WolframRule, RuleParseError> {
+        WolframRule::new(code, states, radius, true)
+    }
+
+    fn new(
+        code: u64,
+        states: u8,
+        radius: u8,
+        totalistic: bool,
+    ) -> Result<WolframRule, RuleParseError> {
+        let cells = 2 * radius as u32 + 1;
+        let cases = if totalistic {
+            (states as u32 - 1) * cells + 1
+        } else {
+            1 << cells
+        };
+        // Each digit of the code in base `states` is the next state of a case.
+        let next = (0..cases)
+            .map(|case| {
+                (states as u64)
+                    .checked_pow(case)
+                    .map_or(0, |place| (code / place % states as u64) as u8)
+            })
+            .collect::<Vec<_>>();
+        if (states as u64)
+            .checked_pow(cases)
+            .is_some_and(|limit| code >= limit)
+        {
+            return Err(RuleParseError::InvalidParameter(code.to_string()));
+        }
+        let radius_offsets = -(radius as i32)..=radius as i32;
+        let offsets = radius_offsets.filter(|&x| x != 0).map(|x| (x, 0)).collect();
+        Ok(WolframRule {
+            code,
+            states,
+            radius,
+            totalistic,
+            neighbourhood: Neighbourhood::Custom(offsets),
+            next,
+        })
+    }
+
+    /// The state of a cell with the states `row` of it and its neighbours, from
+    /// the furthest left to the furthest right.
+    pub fn next_state(&self, row: &[u8]) -> u8 {
+        let case = if self.totalistic {
+            row.iter().map(|&state| state as usize).sum()
+        } else {
+            row.iter()
+                .fold(0, |acc, &state| acc << 1 | (state != 0) as usize)
+        };
+        self.next.get(case).copied().unwrap_or(0)
+    }
+
+    /// Whether a row of dead cells comes alive, so every row has a background.
+    pub fn fills_empty_rows(&self) -> bool {
+        self.next[0] != 0
+    }
+
+    /// Whether `rule` looks like a one dimensional rule, `W` and a number or a
+    /// `C` code with `K` and `R`.
+    pub(crate) fn matches(rule: &str) -> bool {
+        let mut chars = rule.chars();
+        match chars.next() {
+            Some('W' | 'w') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+            Some('C' | 'c') => rule.contains(','),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for WolframRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.totalistic {
+            write!(f, "C{},K{},R{}", self.code, self.states, self.radius)
+        } else {
+            write!(f, "W{}", self.code)
+        }
+    }
+}
+
+impl std::str::FromStr for WolframRule {
+    type Err = RuleParseError;
+
+    /// Parses `W` and an elementary rule number, or `C`, a totalistic code and
+    /// the number of states and range, like `C1635,K3,R1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |part: &str| RuleParseError::InvalidParameter(part.to_string());
+        if let Some(number) = s.strip_prefix(['W', 'w']) {
+            return WolframRule::elementary(number.parse().map_err(|_| invalid(s))?);
+        }
+
+        let (mut code, mut states, mut radius) = (None, 2, 1);
+        for part in s.split(',').map(str::trim) {
+            let mut chars = part.chars();
+            let key = chars.next().map(|c| c.to_ascii_uppercase());
+            let value = chars.as_str();
+            match key {
+                Some('C') => code = Some(value.parse().map_err(|_| invalid(part))?),
+                Some('K') => match value.parse() {
+                    Ok(k) if (2..=MAX_STATES).contains(&k) => states = k,
+                    _ => return Err(invalid(part)),
+                },
+                Some('R') => match value.parse() {
+                    Ok(r) if (1..=MAX_RADIUS).contains(&r) => radius = r,
+                    _ => return Err(invalid(part)),
+                },
+                _ => return Err(invalid(part)),
+            }
+        }
+        let code = code.ok_or(RuleParseError::Malformed)?;
+        WolframRule::totalistic(code, states, radius)
+    }
+}
+
+/// Where a universe's spacetime diagram is up to: the newest row, and the state
+/// every cell of each kept row has away from the pattern.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Rows {
+    /// The row the next generation is computed from, the lowest one.
+    newest: Option<i32>,
+    /// The backgrounds of the kept rows, from the newest up.
+    backgrounds: VecDeque<u8>,
+}
+
+impl Rows {
+    pub fn newest(&self) -> Option<i32> {
+        self.newest
+    }
+
+    /// The state of the cells of row `y` that aren't stored, `0` for rows that
+    /// aren't kept.
+    pub fn background(&self, y: i32) -> u8 {
+        let index = self
+            .newest
+            .and_then(|newest| usize::try_from(y - newest).ok());
+        index
+            .and_then(|i| self.backgrounds.get(i))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Makes the lowest row of `cells` the newest if there isn't one yet, or the
+    /// origin's if there aren't any cells.
+    pub fn start(&mut self, cells: &HashMap<IVec2, u8>) {
+        if self.newest.is_none() {
+            self.newest = Some(cells.keys().map(|pos| pos.y).min().unwrap_or(0));
+            self.backgrounds = VecDeque::from([0]);
+        }
+    }
+
+    /// Moves on to the row below, after `step` has computed it.
+    pub fn advance(&mut self, rule: &WolframRule) {
+        let newest = self.newest.unwrap_or(0);
+        self.backgrounds
+            .push_front(next_background(self.background(newest), rule));
+        self.backgrounds.truncate(HISTORY as usize);
+        self.newest = Some(newest - 1);
+    }
+}
+
+/// The background of the row after one whose background is `background`.
+fn next_background(background: u8, rule: &WolframRule) -> u8 {
+    rule.next_state(&vec![background; 2 * rule.radius as usize + 1])
+}
+
+/// Adds the next generation of the newest row of `rows` as a row below it,
+/// storing the cells that differ from its background.
+///
+/// This changes `cells` in place, as copying every row kept each generation
+/// would take much longer than computing the new one.
+pub(crate) fn step(cells: &mut HashMap<IVec2, u8>, rule: &WolframRule, rows: &Rows) {
+    let row = rows.newest.unwrap_or(0);
+    let background = rows.background(row);
+    let next_background = next_background(background, rule);
+    let radius = rule.radius as i32;
+    let candidates = cells
+        .keys()
+        .filter(|pos| pos.y == row)
+        .flat_map(|pos| pos.x - radius..=pos.x + radius)
+        .collect::<HashSet<_>>();
+
+    let state = |x: i32| {
+        let pos = IVec2::new(x, row);
+        cells.get(&pos).copied().unwrap_or(background)
+    };
+    let mut neighbourhood = Vec::with_capacity(2 * radius as usize + 1);
+    let next = candidates
+        .into_iter()
+        .filter_map(|x| {
+            neighbourhood.clear();
+            neighbourhood.extend((x - radius..=x + radius).map(state));
+            let state = rule.next_state(&neighbourhood);
+            (state != next_background).then_some((IVec2::new(x, row - 1), state))
+        })
+        .collect::<Vec<_>>();
+    // Cells drawn below the newest row are replaced along with the old ones.
+    cells.retain(|pos, _| (row..row - 1 + HISTORY).contains(&pos.y));
+    cells.extend(next);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The newest row of `cells` from `left` to `right`, `.` for cells in the
+    /// background and the state of the others.
+    fn newest_row(cells: &HashMap<IVec2, u8>, rows: &Rows, left: i32, right: i32) -> String {
+        let y = rows.newest().unwrap();
+        (left..=right)
+            .map(|x| match cells.get(&IVec2::new(x, y)) {
+                Some(state) => char::from_digit(*state as u32, 36).unwrap(),
+                None => '.',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rule_30() {
+        let rule = "W30".parse::<WolframRule>().unwrap();
+        let mut cells = HashMap::from_iter([(IVec2::ZERO, 1)]);
+        let mut rows = Rows::default();
+        rows.start(&cells);
+        let mut diagram = vec![newest_row(&cells, &rows, -4, 4)];
+        for _ in 0..4 {
+            step(&mut cells, &rule, &rows);
+            rows.advance(&rule);
+            diagram.push(newest_row(&cells, &rows, -4, 4));
+        }
+        assert_eq!(
+            diagram,
+            [
+                "....1....",
+                "...111...",
+                "..11..1..",
+                ".11.1111.",
+                "11..1...1",
+            ]
+        );
+        // Earlier rows are kept.
+        assert_eq!(cells.len(), 1 + 3 + 3 + 6 + 4);
+    }
+
+    #[test]
+    fn backgrounds_alternate() {
+        let rule = WolframRule::elementary(1).unwrap();
+        assert!(rule.fills_empty_rows());
+        let mut cells = HashMap::from_iter([(IVec2::ZERO, 1)]);
+        let mut rows = Rows::default();
+        rows.start(&cells);
+        for _ in 0..4 {
+            step(&mut cells, &rule, &rows);
+            rows.advance(&rule);
+        }
+        let backgrounds = (-4..=0).map(|y| rows.background(y)).collect::<Vec<_>>();
+        assert_eq!(backgrounds, [0, 1, 0, 1, 0]);
+        // Rule 1 turns 000 on and everything else off, so the single cell
+        // blinks against the background.
+        assert_eq!(newest_row(&cells, &rows, -4, 4), "....1....");
+        let above = (-4..=4).filter(|&x| cells.get(&IVec2::new(x, -3)) == Some(&0));
+        assert_eq!(above.count(), 3);
+        assert_eq!(rows.background(-100), 0);
+    }
+
+    #[test]
+    fn totalistic_codes() {
+        let rule = "C1635,K3,R1".parse::<WolframRule>().unwrap();
+        assert!(rule.totalistic);
+        assert_eq!(rule.neighbourhood.offsets().len(), 2);
+        // 1635 is 2020120 in base 3, read from the right for the sums 0 to 6.
+        let next = (0..=6)
+            .map(|sum: u8| {
+                rule.next_state(&[
+                    sum.min(2),
+                    sum.saturating_sub(2).min(2),
+                    sum.saturating_sub(4),
+                ])
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(next, [0, 2, 1, 0, 2, 0, 2]);
+        assert_eq!(rule.to_string(), "C1635,K3,R1");
+    }
+
+    #[test]
+    fn errors() {
+        for rule in ["W256", "C2187,K3,R1", "C10,K1,R1", "C10,K3,R9", "W"] {
+            assert!(rule.parse::<WolframRule>().is_err(), "{}", rule);
+        }
+        assert!(!WolframRule::matches("B3/S23"));
+        assert!(WolframRule::matches("w110"));
+    }
+}